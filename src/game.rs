@@ -1,8 +1,11 @@
+pub mod bitboard;
 mod board;
 pub mod board_gen;
 mod moves;
 mod piece;
+mod sfen;
 mod turn;
+mod zobrist;
 
 pub use self::board::Board;
 pub use self::moves::Move;
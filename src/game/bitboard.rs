@@ -0,0 +1,210 @@
+use super::*;
+use once_cell::sync::Lazy;
+
+// A square `pos` is represented by the bit `1 << pos`.
+pub const FULL: u32 = (1 << 25) - 1;
+
+pub const fn bit(pos: usize) -> u32 {
+    1 << pos
+}
+
+/// Iterates positions of the set bits.
+pub struct Squares(u32);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let pos = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(pos)
+    }
+}
+
+pub const fn squares(bb: u32) -> Squares {
+    Squares(bb)
+}
+
+pub const fn file(pos: usize) -> u32 {
+    0b00001_00001_00001_00001_00001 << (pos % 5)
+}
+
+/// The rank where pieces of `turn` get promoted.
+pub const fn last_rank(turn: Turn) -> u32 {
+    match turn {
+        Turn::Black => 0b11111,
+        Turn::White => 0b11111 << 20,
+    }
+}
+
+/// Squares which `piece` on `pos` attacks.
+#[inline]
+pub fn attacks(piece: Piece, pos: usize, occupied: u32) -> u32 {
+    let steps = STEP_ATTACKS[piece as usize][pos];
+    match piece.of_turn(Turn::Black) {
+        Piece::BBishop | Piece::BBishopP => steps | slide(pos, occupied, &DIAGONAL),
+        Piece::BRook | Piece::BRookP => steps | slide(pos, occupied, &ORTHOGONAL),
+        _ => steps,
+    }
+}
+
+/// Pieces of `turn` which attack `pos`.
+pub fn attackers(b: &Board, pos: usize, turn: Turn) -> u32 {
+    attackers_through(b, pos, turn, b.occupied())
+}
+
+/// Pieces of `turn` in `occupied` which attack `pos`, as if only they are on the board.
+pub fn attackers_through(b: &Board, pos: usize, turn: Turn, occupied: u32) -> u32 {
+    let mut bb = 0;
+    for p in PIECES[turn as usize] {
+        let pieces = b.pieces[p as usize] & occupied;
+        // attacks are symmetric between the piece and the flipped one.
+        if pieces != 0 {
+            bb |= pieces & attacks(p.flip(), pos, occupied);
+        }
+    }
+    bb
+}
+
+fn slide(pos: usize, occupied: u32, dirs: &[usize]) -> u32 {
+    let mut bb = 0;
+    for d in dirs {
+        let mut ray = RAYS[*d][pos];
+        let blockers = ray & occupied;
+        if blockers != 0 {
+            let blocker = if DIRECTIONS[*d].0 * 5 + DIRECTIONS[*d].1 > 0 {
+                blockers.trailing_zeros()
+            } else {
+                31 - blockers.leading_zeros()
+            };
+            // squares behind the first blocker can't be reached.
+            ray ^= RAYS[*d][blocker as usize];
+        }
+        bb |= ray;
+    }
+    bb
+}
+
+/// Pieces of each turn, except absent and invalid.
+pub const PIECES: [[Piece; 10]; 2] = [
+    [
+        Piece::BKing,
+        Piece::BGold,
+        Piece::BSilver,
+        Piece::BBishop,
+        Piece::BRook,
+        Piece::BPawn,
+        Piece::BSilverP,
+        Piece::BBishopP,
+        Piece::BRookP,
+        Piece::BPawnP,
+    ],
+    [
+        Piece::WKing,
+        Piece::WGold,
+        Piece::WSilver,
+        Piece::WBishop,
+        Piece::WRook,
+        Piece::WPawn,
+        Piece::WSilverP,
+        Piece::WBishopP,
+        Piece::WRookP,
+        Piece::WPawnP,
+    ],
+];
+
+// (dy, dx) for black. forward is -1.
+const DIRECTIONS: [(i8, i8); 8] = [
+    (-1, 0),
+    (0, -1),
+    (0, 1),
+    (1, 0),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+const ORTHOGONAL: [usize; 4] = [0, 1, 2, 3];
+const DIAGONAL: [usize; 4] = [4, 5, 6, 7];
+
+// The followings are cache
+
+static RAYS: Lazy<[[u32; 25]; 8]> = Lazy::new(|| {
+    let mut rays = [[0; 25]; 8];
+    for (d, (dy, dx)) in DIRECTIONS.iter().enumerate() {
+        for (pos, ray) in rays[d].iter_mut().enumerate() {
+            let (mut y, mut x) = ((pos / 5) as i8 + dy, (pos % 5) as i8 + dx);
+            while (0..5).contains(&y) && (0..5).contains(&x) {
+                *ray |= bit((y * 5 + x) as usize);
+                y += dy;
+                x += dx;
+            }
+        }
+    }
+    rays
+});
+
+static STEP_ATTACKS: Lazy<[[u32; 25]; 22]> = Lazy::new(|| {
+    let king: Vec<usize> = (0..8).collect();
+    let gold = vec![0, 1, 2, 3, 4, 5];
+    let silver = vec![0, 4, 5, 6, 7];
+    let pawn = vec![0];
+
+    let mut table = [[0; 25]; 22];
+    for i in 2..=21 {
+        let piece = Piece::from_u8(i);
+        let steps = match piece.of_turn(Turn::Black) {
+            Piece::BKing => king.clone(),
+            Piece::BGold | Piece::BSilverP | Piece::BPawnP => gold.clone(),
+            Piece::BSilver => silver.clone(),
+            Piece::BPawn => pawn.clone(),
+            Piece::BBishopP => ORTHOGONAL.to_vec(),
+            Piece::BRookP => DIAGONAL.to_vec(),
+            _ => vec![],
+        };
+        // white moves are mirrored vertically.
+        let sign = piece.turn().val();
+        for (pos, bb) in table[i as usize].iter_mut().enumerate() {
+            for d in &steps {
+                let (dy, dx) = DIRECTIONS[*d];
+                let (y, x) = ((pos / 5) as i8 + dy * sign, (pos % 5) as i8 + dx);
+                if (0..5).contains(&y) && (0..5).contains(&x) {
+                    *bb |= bit((y * 5 + x) as usize);
+                }
+            }
+        }
+    }
+    table
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliding_attacks_stop_at_blocker() {
+        // rook on 3c, pieces on 3a and 5c.
+        let occupied = bit(2) | bit(10) | bit(12);
+        let bb = attacks(Piece::BRook, 12, occupied);
+        let expected = [2, 7, 10, 11, 13, 14, 17, 22];
+        assert_eq!(squares(bb).collect::<Vec<_>>(), expected);
+
+        let bb = attacks(Piece::WBishopP, 12, occupied);
+        let expected = [0, 4, 6, 7, 8, 11, 13, 16, 17, 18, 20, 24];
+        assert_eq!(squares(bb).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn white_steps_are_mirrored() {
+        assert_eq!(attacks(Piece::BPawn, 12, 0), bit(7));
+        assert_eq!(attacks(Piece::WPawn, 12, 0), bit(17));
+        assert_eq!(
+            attacks(Piece::WSilver, 12, 0),
+            bit(6) | bit(8) | bit(16) | bit(17) | bit(18)
+        );
+    }
+}
@@ -1,109 +1,229 @@
-use super::{board_gen, Move, Piece, Turn};
+use super::{bitboard, board_gen, zobrist, Move, Piece, Turn};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Board {
-    pub squares: [Piece; 25],
-    pub hands: [Piece; 10],
+    squares: [Piece; 25],
+    // number of pieces in hands, indexed by turn and `Piece::hand_index`.
+    pub hands: [[u8; 5]; 2],
+    // bitboards of each piece and each side, which are kept in sync with squares.
+    pub pieces: [u32; 22],
+    pub colors: [u32; 2],
     pub turn: Turn,
     pub won: Option<Turn>,
+    pub move_number: u32,
+    // Zobrist hash of squares, hands and side to move, updated incrementally.
+    pub hash: u64,
+    // hashes before each move in this game, with the move and how to undo it.
+    pub history: Vec<(u64, Move, Undo)>,
+    // repetitions are looked for only in the history from here, which is after
+    // a null move in the search.
+    history_floor: usize,
 }
 
 impl Board {
     pub fn init() -> Board {
-        board_gen::from_str("11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk")
+        board_gen::from_str("11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk").unwrap()
     }
 
     pub fn empty() -> Board {
         Board {
             squares: [Piece::Absent; 25],
-            hands: [Piece::Absent; 10],
+            hands: [[0; 5]; 2],
+            pieces: [0; 22],
+            colors: [0; 2],
             turn: Turn::Black,
             won: None,
+            move_number: 1,
+            hash: 0,
+            history: Vec::new(),
+            history_floor: 0,
         }
     }
 
-    pub fn copy_from(&mut self, from: &Board) {
-        self.squares = from.squares;
-        self.turn = from.turn;
-        self.hands = from.hands;
-    }
-
     pub const fn at(&self, pos: usize) -> Piece {
         self.squares[pos]
     }
 
-    pub fn put_move(&mut self, m: &Move) {
+    pub const fn squares(&self) -> &[Piece; 25] {
+        &self.squares
+    }
+
+    /// Puts `piece` on `pos`. Squares must be updated only by this.
+    pub fn set(&mut self, pos: usize, piece: Piece) {
+        let old = self.squares[pos];
+        self.hash ^= zobrist::KEYS.square(pos, old) ^ zobrist::KEYS.square(pos, piece);
+        if !old.is_absent() {
+            self.pieces[old as usize] &= !(1 << pos);
+            self.colors[old.turn() as usize] &= !(1 << pos);
+        }
+        if !piece.is_absent() {
+            self.pieces[piece as usize] |= 1 << pos;
+            self.colors[piece.turn() as usize] |= 1 << pos;
+        }
+        self.squares[pos] = piece;
+    }
+
+    /// Returns the number of `piece` in hands of its turn.
+    #[inline]
+    pub const fn hand(&self, piece: Piece) -> u8 {
+        self.hands[piece.turn_index()][piece.hand_index()]
+    }
+
+    fn add_hand(&mut self, piece: Piece, n: i8) {
+        let count = &mut self.hands[piece.turn_index()][piece.hand_index()];
+        let old = *count;
+        *count = (old as i8 + n) as u8;
+        self.hash ^= zobrist::KEYS.hand(piece, old) ^ zobrist::KEYS.hand(piece, *count);
+    }
+
+    fn set_turn(&mut self, turn: Turn) {
+        if self.turn != turn {
+            self.hash ^= zobrist::KEYS.white_to_move;
+        }
+        self.turn = turn;
+    }
+
+    /// Computes the hash from scratch, which `hash` must always equal.
+    pub fn zobrist_hash(&self) -> u64 {
+        zobrist::hash(self)
+    }
+
+    #[inline]
+    pub const fn occupied(&self) -> u32 {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn put_move(&mut self, m: &Move) -> Undo {
+        let key = self.hash;
         let took = self.squares[m.dst as usize];
+        let undo = Undo {
+            took,
+            won: self.won,
+            history_len: self.history.len(),
+        };
 
-        if m.src >= 100 {
+        if m.is_drop() {
             // from hands
-            self.hands[(m.src - 100) as usize] = Piece::Absent;
+            self.add_hand(m.piece, -1);
         } else {
             // move in board
-            self.squares[m.src as usize] = Piece::Absent;
+            self.set(m.src as usize, Piece::Absent);
         }
         if m.promote {
-            self.squares[m.dst as usize] = Piece::from_u8(m.piece as u8 + 8);
+            self.set(m.dst as usize, Piece::from_u8(m.piece as u8 + 8));
         } else {
-            self.squares[m.dst as usize] = m.piece;
+            self.set(m.dst as usize, m.piece);
         }
 
-        self.turn = self.turn.next();
+        self.set_turn(self.turn.next());
+        self.move_number += 1;
 
         if took != Piece::Absent {
-            for i in 0..10 {
-                if self.hands[i] == Piece::Absent {
-                    self.hands[i] = if took.as_u8() >= 14 {
-                        Piece::from_u8(took.flip() as u8 - 8)
-                    } else {
-                        took.flip()
-                    };
-                    break;
-                }
-            }
             if took.of_turn(Turn::Black) == Piece::BKing {
                 self.won = Some(took.flip().turn());
+            } else {
+                self.add_hand(took.unpromote().flip(), 1);
             }
         }
+        debug_assert_eq!(self.hash, self.zobrist_hash());
+
+        self.history.push((key, *m, undo));
+        if self.won.is_none() && self.repetition_count() >= 4 {
+            self.won = Some(self.sennichite_winner());
+        }
+        undo
     }
 
-    #[allow(unused)]
-    pub fn flip_turn(&mut self) -> &Board {
-        self.turn = self.turn.next();
-        self
+    /// Takes back `m`, which must be the last move put with `put_move`.
+    pub fn undo_move(&mut self, m: &Move, undo: Undo) {
+        self.history.truncate(undo.history_len);
+        self.won = undo.won;
+        self.move_number -= 1;
+        self.set_turn(self.turn.next());
+
+        let took = undo.took;
+        if !took.is_absent() && took.of_turn(Turn::Black) != Piece::BKing {
+            self.add_hand(took.unpromote().flip(), -1);
+        }
+        self.set(m.dst as usize, took);
+        if m.is_drop() {
+            self.add_hand(m.piece, 1);
+        } else {
+            self.set(m.src as usize, m.piece);
+        }
+        debug_assert_eq!(self.hash, self.zobrist_hash());
     }
 
-    // There are 23 absent squares and it uses 23 bits.
-    // There are 12 occupied squares and it uses 12*6=72bits.
-    // 1(turn) + 23(empty) + 72(occupied) = 96bits.
-    #[allow(unused)]
-    pub fn u128_repr(&self) -> u128 {
-        let mut hash = 0u128;
-        hash |= self.turn as u128;
-        for p in self.squares {
-            if p.is_absent() {
-                // absent is represented by 0
-                hash <<= 1;
-            } else {
-                // piece is reprersented by 1|piece
-                hash <<= 6;
-                hash |= 1 << 5;
-                hash |= p as u128;
-            }
+    /// Returns how many times the current position has appeared in this game.
+    pub fn repetition_count(&self) -> usize {
+        let key = self.hash;
+        self.history[self.history_floor..]
+            .iter()
+            .filter(|(k, _, _)| *k == key)
+            .count()
+            + 1
+    }
+
+    /// Returns the winner when the current position is regarded as sennichite.
+    ///
+    /// The side which has kept checking since the position first appeared loses.
+    /// Otherwise black loses, as the first player can't draw by repetition in gogo shogi.
+    pub fn sennichite_winner(&self) -> Turn {
+        let key = self.hash;
+        let history = &self.history[self.history_floor..];
+        let first = match history.iter().position(|(k, _, _)| *k == key) {
+            Some(i) => self.history_floor + i,
+            None => return Turn::White,
+        };
+        // go back to the first appearance to see which moves were check.
+        let mut board = self.clone();
+        let mut checking = [true, true];
+        while board.history.len() > first {
+            let (_, m, undo) = *board.history.last().unwrap();
+            checking[board.turn.next() as usize] &= board.king_attacked();
+            board.undo_move(&m, undo);
         }
-        let mut hands_sorted = self.hands;
-        hands_sorted.sort();
-        for p in hands_sorted {
-            if p.is_absent() {
-                hash <<= 1;
-            } else {
-                hash <<= 6;
-                hash |= 1 << 5;
-                hash |= p as u128;
-            }
+        if checking[Turn::White as usize] && !checking[Turn::Black as usize] {
+            Turn::Black
+        } else {
+            Turn::White
         }
-        hash
     }
+
+    // whether the king of the side to move is attacked.
+    fn king_attacked(&self) -> bool {
+        let king = self.pieces[Piece::BKing.of_turn(self.turn) as usize];
+        king != 0
+            && bitboard::attackers(self, king.trailing_zeros() as usize, self.turn.next()) != 0
+    }
+
+    /// Passes the turn in the search, returning how to undo it by `undo_null_move`.
+    ///
+    /// Positions before it are not repeated by the following moves, as the pass
+    /// is not a move of the game.
+    pub fn put_null_move(&mut self) -> usize {
+        self.set_turn(self.turn.next());
+        std::mem::replace(&mut self.history_floor, self.history.len())
+    }
+
+    pub fn undo_null_move(&mut self, history_floor: usize) {
+        self.history_floor = history_floor;
+        self.set_turn(self.turn.next());
+    }
+
+    #[allow(unused)]
+    pub fn flip_turn(&mut self) -> &Board {
+        self.set_turn(self.turn.next());
+        self
+    }
+}
+
+/// What `put_move` has overwritten, to restore the board by `undo_move`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Undo {
+    took: Piece,
+    won: Option<Turn>,
+    history_len: usize,
 }
 
 impl std::fmt::Display for Board {
@@ -122,11 +242,144 @@ impl std::fmt::Display for Board {
             ));
         }
         buf.push_str("Hands: ");
-        for h in self.hands {
-            if h != Piece::Absent {
-                buf.push_str(&format!("{} ", h.to_str()));
+        for turn in [Turn::Black, Turn::White] {
+            for p in Piece::HAND_PIECES {
+                let p = p.of_turn(turn);
+                for _ in 0..self.hand(p) {
+                    buf.push_str(&format!("{} ", p.to_str()));
+                }
             }
         }
         write!(f, "{}", buf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(b: &mut Board, moves: &[Move], times: usize) {
+        for _ in 0..times {
+            for m in moves {
+                assert_eq!(b.won, None);
+                b.put_move(m);
+            }
+        }
+    }
+
+    #[test]
+    fn sennichite_is_lose_of_black() {
+        let mut b = board_gen::from_str("11wk,55bk").unwrap();
+        let moves = [
+            Move::new(&Piece::BKing, 20, 15, false),
+            Move::new(&Piece::WKing, 4, 9, false),
+            Move::new(&Piece::BKing, 15, 20, false),
+            Move::new(&Piece::WKing, 9, 4, false),
+        ];
+        play(&mut b, &moves, 2);
+        assert_eq!(b.repetition_count(), 3);
+        play(&mut b, &moves, 1);
+        assert_eq!(b.repetition_count(), 4);
+        assert_eq!(b.won, Some(Turn::White));
+    }
+
+    #[test]
+    fn repetition_does_not_cross_null_move() {
+        let mut b = board_gen::from_str("11wk,55bk").unwrap();
+        let first = b.hash;
+        let before = [
+            Move::new(&Piece::BKing, 20, 21, false),
+            Move::new(&Piece::WKing, 4, 3, false),
+        ];
+        play(&mut b, &before, 1);
+        // the same position is repeated without passing.
+        let mut without_null = b.clone();
+        let back = [
+            Move::new(&Piece::BKing, 21, 20, false),
+            Move::new(&Piece::WKing, 3, 4, false),
+        ];
+        play(&mut without_null, &back, 1);
+        assert_eq!(without_null.hash, first);
+        assert_eq!(without_null.repetition_count(), 2);
+
+        let hash = b.hash;
+        let undo = b.put_null_move();
+        let after = [
+            Move::new(&Piece::WKing, 3, 8, false),
+            Move::new(&Piece::BKing, 21, 20, false),
+            Move::new(&Piece::WKing, 8, 4, false),
+        ];
+        play(&mut b, &after, 1);
+        assert_eq!(b.hash, first);
+        assert_eq!(b.repetition_count(), 1);
+        for m in after.iter().rev() {
+            let (_, _, u) = *b.history.last().unwrap();
+            b.undo_move(m, u);
+        }
+        b.undo_null_move(undo);
+        assert_eq!(b.hash, hash);
+        play(&mut b, &back, 1);
+        assert_eq!(b.repetition_count(), 2);
+    }
+
+    #[test]
+    fn perpetual_check_is_lose() {
+        let mut b = board_gen::from_str("11wk,41wr,55bk,w").unwrap();
+        let moves = [
+            Move::new(&Piece::WRook, 1, 0, false),
+            Move::new(&Piece::BKing, 20, 21, false),
+            Move::new(&Piece::WRook, 0, 1, false),
+            Move::new(&Piece::BKing, 21, 20, false),
+        ];
+        play(&mut b, &moves, 3);
+        assert_eq!(b.won, Some(Turn::Black));
+    }
+
+    #[test]
+    fn hash_is_same_for_same_position() {
+        let mut b = Board::init();
+        let moves = [
+            Move::new(&Piece::BPawn, 15, 10, false),
+            Move::new(&Piece::WPawn, 9, 14, false),
+            Move::new(&Piece::BGold, 21, 16, false),
+        ];
+        b.put_move(&moves[0]);
+        b.put_move(&moves[1]);
+        b.put_move(&moves[2]);
+        let mut other = Board::init();
+        other.put_move(&moves[2]);
+        other.put_move(&moves[1]);
+        other.put_move(&moves[0]);
+        assert_eq!(b.hash, other.hash);
+        assert_eq!(b.hash, Board::from_sfen(&b.to_sfen()).unwrap().hash);
+
+        other.flip_turn();
+        assert_ne!(b.hash, other.hash);
+    }
+
+    #[test]
+    fn undo_moves() {
+        let mut b = board_gen::from_str("31wk,33wp,42bp,15bk,__bb,__wg").unwrap();
+        let original = b.clone();
+        let moves = [
+            Move::new(&Piece::BPawn, 6, 1, true), // promote
+            Move::drop(&Piece::WGold, 7),
+            Move::drop(&Piece::BBishop, 18),
+            Move::new(&Piece::WGold, 7, 8, false),
+            Move::new(&Piece::BBishop, 18, 12, false), // take
+            Move::new(&Piece::WKing, 2, 1, false),     // take promoted
+        ];
+        let mut undos = vec![];
+        let mut boards = vec![];
+        for m in &moves {
+            boards.push(b.clone());
+            undos.push(b.put_move(m));
+        }
+        assert_eq!(b.hands, [[0, 0, 0, 0, 1], [0, 0, 0, 0, 1]]);
+        for m in moves.iter().rev() {
+            b.undo_move(m, undos.pop().unwrap());
+            assert_eq!(b, boards.pop().unwrap());
+        }
+        assert_eq!(b, original);
+    }
+}
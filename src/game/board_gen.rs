@@ -1,4 +1,67 @@
 use super::{Board, Piece, Turn};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Token is not formatted as `<file><rank><turn><piece>`.
+    InvalidToken {
+        token: String,
+        at: usize,
+    },
+    UnknownPiece {
+        token: String,
+        at: usize,
+    },
+    DuplicateSquare {
+        token: String,
+        at: usize,
+    },
+    InvalidHand {
+        token: String,
+        at: usize,
+    },
+    TooManyHands,
+    TooManyPieces(Piece),
+    MissingKing(Turn),
+    /// SFEN doesn't have 3 or 4 fields, or 5 ranks.
+    InvalidSfen(String),
+    InvalidRank(String),
+    InvalidSfenPiece(char),
+    InvalidTurn(String),
+    /// Hands end with a count without a piece.
+    InvalidHands(String),
+    InvalidMoveNumber(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidToken { token, at } => {
+                write!(f, "invalid token `{}` at {}", token, at)
+            }
+            ParseError::UnknownPiece { token, at } => {
+                write!(f, "unknown piece in `{}` at {}", token, at)
+            }
+            ParseError::DuplicateSquare { token, at } => {
+                write!(f, "square is already occupied by `{}` at {}", token, at)
+            }
+            ParseError::InvalidHand { token, at } => {
+                write!(f, "piece can't be in hands `{}` at {}", token, at)
+            }
+            ParseError::TooManyHands => write!(f, "more than 10 pieces in hands"),
+            ParseError::TooManyPieces(p) => write!(f, "too many {:?}", p),
+            ParseError::MissingKing(t) => write!(f, "{:?} king is missing", t),
+            ParseError::InvalidSfen(s) => {
+                write!(f, "SFEN must have 3 or 4 fields and 5 ranks: {}", s)
+            }
+            ParseError::InvalidRank(rank) => write!(f, "rank must have 5 squares: {}", rank),
+            ParseError::InvalidSfenPiece(c) => write!(f, "invalid piece {}", c),
+            ParseError::InvalidTurn(t) => write!(f, "invalid side to move: {}", t),
+            ParseError::InvalidHands(hands) => write!(f, "hands end with a number: {}", hands),
+            ParseError::InvalidMoveNumber(n) => write!(f, "invalid move number: {}", n),
+        }
+    }
+}
 
 /// Parses board expression
 ///
@@ -7,29 +70,114 @@ use super::{Board, Piece, Turn};
 ///
 /// Promoted piece is expressed as `35bS`.
 /// Piece in hands is expressed as `__bs`.
-pub fn from_str(s: &str) -> Board {
+/// Side to move is expressed as `w` or `b` (black by default).
+pub fn from_str(s: &str) -> Result<Board, ParseError> {
     let mut board = Board::empty();
-    let chars = s.as_bytes();
-    for i in 0..=(chars.len() / 5) {
-        let turn = if chars[i * 5 + 2] == b'b' {
-            Turn::Black
-        } else {
-            Turn::White
+    let mut hands = 0;
+    let mut at = 0;
+    for raw in s.split(',') {
+        let token = raw.trim();
+        let token_at = at + raw.len() - raw.trim_start().len();
+        at += raw.len() + 1;
+        let invalid = || ParseError::InvalidToken {
+            token: token.to_string(),
+            at: token_at,
         };
-        let piece = piece_repr(chars[i * 5 + 3]);
-        if chars[i * 5] == b'_' {
-            for i in 0..10 {
-                if board.hands[i].is_absent() {
-                    board.hands[i] = piece.of_turn(turn);
-                    break;
+
+        match token {
+            "" => continue,
+            "b" => board.turn = Turn::Black,
+            "w" => board.turn = Turn::White,
+            _ => {
+                let chars = token.as_bytes();
+                if chars.len() != 4 {
+                    return Err(invalid());
+                }
+                let turn = match chars[2] {
+                    b'b' => Turn::Black,
+                    b'w' => Turn::White,
+                    _ => return Err(invalid()),
+                };
+                let piece = match piece_repr(chars[3]) {
+                    Piece::Invalid => {
+                        return Err(ParseError::UnknownPiece {
+                            token: token.to_string(),
+                            at: token_at,
+                        })
+                    }
+                    p => p.of_turn(turn),
+                };
+                match (chars[0], chars[1]) {
+                    (b'_', b'_') => {
+                        if piece.of_turn(Turn::Black) == Piece::BKing
+                            || piece.as_u8() >= Piece::BSilverP.as_u8()
+                        {
+                            return Err(ParseError::InvalidHand {
+                                token: token.to_string(),
+                                at: token_at,
+                            });
+                        }
+                        if hands >= 10 {
+                            return Err(ParseError::TooManyHands);
+                        }
+                        board.hands[piece.turn_index()][piece.hand_index()] += 1;
+                        hands += 1;
+                    }
+                    (b'1'..=b'5', b'1'..=b'5') => {
+                        let pos = ((chars[1] - b'1') * 5 + (b'5' - chars[0])) as usize;
+                        if !board.at(pos).is_absent() {
+                            return Err(ParseError::DuplicateSquare {
+                                token: token.to_string(),
+                                at: token_at,
+                            });
+                        }
+                        board.set(pos, piece);
+                    }
+                    _ => return Err(invalid()),
                 }
             }
-        } else {
-            let pos = (chars[i * 5 + 1] - b'0' - 1) * 5 + (5 - (chars[i * 5] - b'0'));
-            board.squares[pos as usize] = piece.of_turn(turn);
-        };
+        }
+    }
+    validate(&board)?;
+    board.hash = board.zobrist_hash();
+    Ok(board)
+}
+
+/// Checks that the number of each piece is possible in gogo shogi.
+pub fn validate(b: &Board) -> Result<(), ParseError> {
+    // each kind of piece exists only 2, including promoted and ones in hands.
+    let mut counts = [0; 22];
+    for &p in b.squares() {
+        if !p.is_absent() {
+            counts[p.of_turn(Turn::Black).unpromote() as usize] += 1;
+        }
     }
-    board
+    for p in Piece::HAND_PIECES {
+        for turn in [Turn::Black, Turn::White] {
+            counts[p as usize] += b.hand(p.of_turn(turn)) as usize;
+        }
+        if counts[p as usize] > 2 {
+            return Err(ParseError::TooManyPieces(p));
+        }
+    }
+
+    for turn in [Turn::Black, Turn::White] {
+        let king = Piece::BKing.of_turn(turn);
+        match b.squares().iter().filter(|p| **p == king).count() {
+            0 => return Err(ParseError::MissingKing(turn)),
+            1 => {}
+            _ => return Err(ParseError::TooManyPieces(king)),
+        }
+    }
+    Ok(())
+}
+
+/// Parses a board for tests, and prints it to see when the test fails.
+#[cfg(test)]
+pub fn gen(s: &str) -> Board {
+    let b = from_str(s).unwrap();
+    println!("{}", b);
+    b
 }
 
 pub fn piece_repr(p: u8) -> Piece {
@@ -54,14 +202,72 @@ mod tests {
 
     #[test]
     fn test_with_hands() {
-        let generated = from_str("31wk,33bp,15bk,__bg,__wp");
+        let generated = from_str("31wk,33bp,15bk,__bg,__wp").unwrap();
         let mut expected = Board::empty();
-        expected.squares[2] = Piece::WKing;
-        expected.squares[12] = Piece::BPawn;
-        expected.squares[24] = Piece::BKing;
-        expected.hands[0] = Piece::BGold;
-        expected.hands[1] = Piece::WPawn;
+        expected.set(2, Piece::WKing);
+        expected.set(12, Piece::BPawn);
+        expected.set(24, Piece::BKing);
+        expected.hands[0][Piece::BGold.hand_index()] = 1;
+        expected.hands[1][Piece::WPawn.hand_index()] = 1;
+        expected.hash = expected.zobrist_hash();
         assert_eq!(generated, expected);
         println!("{}", generated);
     }
+
+    #[test]
+    fn test_with_turn() {
+        let generated = from_str("31wk, 15bk, w,").unwrap();
+        assert_eq!(generated.turn, Turn::White);
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        let invalid = |token: &str, at| ParseError::InvalidToken {
+            token: token.to_string(),
+            at,
+        };
+        assert_eq!(from_str("31wk,15b"), Err(invalid("15b", 5)));
+        assert_eq!(from_str("31wk,61bk"), Err(invalid("61bk", 5)));
+        assert_eq!(from_str("31wk,15xk"), Err(invalid("15xk", 5)));
+        assert_eq!(
+            from_str("31wk,15bx"),
+            Err(ParseError::UnknownPiece {
+                token: "15bx".to_string(),
+                at: 5
+            })
+        );
+        assert_eq!(
+            from_str("31wk,15bk,31bg"),
+            Err(ParseError::DuplicateSquare {
+                token: "31bg".to_string(),
+                at: 10
+            })
+        );
+        assert_eq!(
+            from_str("31wk,15bk,__bk"),
+            Err(ParseError::InvalidHand {
+                token: "__bk".to_string(),
+                at: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_impossible_counts() {
+        assert_eq!(from_str("31wk"), Err(ParseError::MissingKing(Turn::Black)));
+        assert_eq!(
+            from_str("31wk,15bk,11bk"),
+            Err(ParseError::TooManyPieces(Piece::BKing))
+        );
+        assert_eq!(
+            from_str("31wk,15bk,11bp,12wP,__bp"),
+            Err(ParseError::TooManyPieces(Piece::BPawn))
+        );
+        let hands = "__bg,__bg,__bs,__bs,__bb,__bb,__br,__br,__bp,__bp";
+        assert!(from_str(&format!("31wk,15bk,{}", hands)).is_ok());
+        assert_eq!(
+            from_str(&format!("31wk,15bk,{},__wp", hands)),
+            Err(ParseError::TooManyHands)
+        );
+    }
 }
@@ -1,4 +1,5 @@
-use super::Piece;
+use super::{Piece, Turn};
+use std::fmt;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Move {
@@ -9,6 +10,9 @@ pub struct Move {
 }
 
 impl Move {
+    /// `src` of dropping moves.
+    pub const HAND: u8 = 100;
+
     pub fn new(piece: &Piece, src: u8, dst: u8, promote: bool) -> Move {
         Move {
             piece: *piece,
@@ -17,4 +21,48 @@ impl Move {
             promote,
         }
     }
+
+    pub fn drop(piece: &Piece, dst: u8) -> Move {
+        Move::new(piece, Move::HAND, dst, false)
+    }
+
+    #[inline]
+    pub const fn is_drop(&self) -> bool {
+        self.src == Move::HAND
+    }
+}
+
+/// Formats like `3332`, `3231p` or `g32`, as the CUI accepts.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let square = |pos: u8| format!("{}{}", 5 - pos % 5, pos / 5 + 1);
+        if self.is_drop() {
+            let letter = match self.piece.of_turn(Turn::Black) {
+                Piece::BGold => 'g',
+                Piece::BSilver => 's',
+                Piece::BBishop => 'b',
+                Piece::BRook => 'r',
+                _ => 'p',
+            };
+            write!(f, "{}{}", letter, square(self.dst))
+        } else {
+            let promote = if self.promote { "p" } else { "" };
+            write!(f, "{}{}{}", square(self.src), square(self.dst), promote)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_as_cui_input() {
+        assert_eq!(
+            Move::new(&Piece::BBishop, 23, 17, false).to_string(),
+            "2534"
+        );
+        assert_eq!(Move::new(&Piece::BPawn, 7, 2, true).to_string(), "3231p");
+        assert_eq!(Move::drop(&Piece::WGold, 12).to_string(), "g33");
+    }
 }
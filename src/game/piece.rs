@@ -31,6 +31,15 @@ pub enum Piece {
 }
 
 impl Piece {
+    /// Pieces which can be in hands, in order of `hand_index`.
+    pub const HAND_PIECES: [Piece; 5] = [
+        Piece::BGold,
+        Piece::BSilver,
+        Piece::BBishop,
+        Piece::BRook,
+        Piece::BPawn,
+    ];
+
     #[inline]
     pub const fn is_absent(self) -> bool {
         self.as_u8() == 0
@@ -48,13 +57,18 @@ impl Piece {
 
     #[inline]
     pub fn turn(self) -> Turn {
-        if self as u8 % 2 == 0 {
+        if (self as u8).is_multiple_of(2) {
             Turn::Black
         } else {
             Turn::White
         }
     }
 
+    #[inline]
+    pub const fn turn_index(self) -> usize {
+        self.as_u8() as usize % 2
+    }
+
     #[inline]
     pub fn of_turn(self, turn: Turn) -> Piece {
         if self.turn() == turn {
@@ -66,13 +80,28 @@ impl Piece {
 
     #[inline]
     pub fn flip(self) -> Piece {
-        if self as u8 % 2 == 0 {
+        if (self as u8).is_multiple_of(2) {
             Piece::from_u8(self as u8 + 1)
         } else {
             Piece::from_u8(self as u8 - 1)
         }
     }
 
+    #[inline]
+    pub fn unpromote(self) -> Piece {
+        if self.as_u8() >= Piece::BSilverP.as_u8() {
+            Piece::from_u8(self.as_u8() - 8)
+        } else {
+            self
+        }
+    }
+
+    /// Index of gold, silver, bishop, rook and pawn in hands of each turn.
+    #[inline]
+    pub const fn hand_index(self) -> usize {
+        (self.as_u8() as usize - Piece::BGold.as_u8() as usize) / 2
+    }
+
     pub fn to_str(self) -> &'static str {
         &BOARD_REPR[self as usize]
     }
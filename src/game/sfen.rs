@@ -0,0 +1,247 @@
+use super::board_gen::{self, ParseError};
+use super::{Board, Piece, Turn};
+
+// hand pieces are written in this order, as most shogi software does.
+const HAND_ORDER: [Piece; 5] = [
+    Piece::BRook,
+    Piece::BBishop,
+    Piece::BGold,
+    Piece::BSilver,
+    Piece::BPawn,
+];
+
+impl Board {
+    /// Parses 5x5 SFEN like `rbsgk/4p/5/P4/KGSBR b - 1`.
+    ///
+    /// The move number can be omitted and defaults to 1.
+    pub fn from_sfen(s: &str) -> Result<Board, ParseError> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() < 3 || fields.len() > 4 {
+            return Err(ParseError::InvalidSfen(s.to_string()));
+        }
+        let mut board = Board::empty();
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 5 {
+            return Err(ParseError::InvalidSfen(s.to_string()));
+        }
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+            let mut promoted = false;
+            for c in rank.chars() {
+                if x >= 5 {
+                    return Err(ParseError::InvalidRank(rank.to_string()));
+                }
+                match c {
+                    '1'..='5' if !promoted => x += c as usize - '0' as usize,
+                    '+' if !promoted => promoted = true,
+                    _ => {
+                        let piece =
+                            sfen_piece(c, promoted).ok_or(ParseError::InvalidSfenPiece(c))?;
+                        board.set(y * 5 + x, piece);
+                        x += 1;
+                        promoted = false;
+                    }
+                }
+            }
+            if x != 5 || promoted {
+                return Err(ParseError::InvalidRank(rank.to_string()));
+            }
+        }
+
+        board.turn = match fields[1] {
+            "b" => Turn::Black,
+            "w" => Turn::White,
+            t => return Err(ParseError::InvalidTurn(t.to_string())),
+        };
+
+        if fields[2] != "-" {
+            let mut total = 0;
+            let mut count = 0;
+            for c in fields[2].chars() {
+                if let Some(d) = c.to_digit(10) {
+                    count = count * 10 + d as usize;
+                    continue;
+                }
+                let piece = sfen_piece(c, false)
+                    .filter(|p| p.of_turn(Turn::Black) != Piece::BKing)
+                    .ok_or(ParseError::InvalidSfenPiece(c))?;
+                total += count.max(1);
+                if total > 10 {
+                    return Err(ParseError::TooManyHands);
+                }
+                board.hands[piece.turn_index()][piece.hand_index()] += count.max(1) as u8;
+                count = 0;
+            }
+            if count != 0 {
+                return Err(ParseError::InvalidHands(fields[2].to_string()));
+            }
+        }
+
+        if let Some(n) = fields.get(3) {
+            board.move_number = n
+                .parse()
+                .map_err(|_| ParseError::InvalidMoveNumber(n.to_string()))?;
+        }
+        board_gen::validate(&board)?;
+        board.hash = board.zobrist_hash();
+        Ok(board)
+    }
+
+    /// Serializes the board as 5x5 SFEN.
+    pub fn to_sfen(&self) -> String {
+        let mut buf = String::new();
+        for y in 0..5 {
+            if y > 0 {
+                buf.push('/');
+            }
+            let mut empty = 0;
+            for x in 0..5 {
+                let piece = self.at(y * 5 + x);
+                if piece.is_absent() {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    buf.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                buf.push_str(&sfen_str(piece));
+            }
+            if empty > 0 {
+                buf.push_str(&empty.to_string());
+            }
+        }
+
+        buf.push_str(match self.turn {
+            Turn::Black => " b ",
+            Turn::White => " w ",
+        });
+
+        let mut hands = String::new();
+        for turn in [Turn::Black, Turn::White] {
+            for piece in HAND_ORDER {
+                let piece = piece.of_turn(turn);
+                let count = self.hand(piece);
+                if count > 1 {
+                    hands.push_str(&count.to_string());
+                }
+                if count > 0 {
+                    hands.push_str(&sfen_str(piece));
+                }
+            }
+        }
+        if hands.is_empty() {
+            hands.push('-');
+        }
+        buf.push_str(&hands);
+
+        buf.push_str(&format!(" {}", self.move_number));
+        buf
+    }
+}
+
+fn sfen_piece(c: char, promoted: bool) -> Option<Piece> {
+    if !c.is_ascii_alphabetic() {
+        return None;
+    }
+    let turn = if c.is_ascii_uppercase() {
+        Turn::Black
+    } else {
+        Turn::White
+    };
+    let piece = board_gen::piece_repr(c.to_ascii_lowercase() as u8);
+    if piece == Piece::Invalid {
+        return None;
+    }
+    if !promoted {
+        return Some(piece.of_turn(turn));
+    }
+    match piece {
+        Piece::BSilver | Piece::BBishop | Piece::BRook | Piece::BPawn => {
+            Some(Piece::from_u8(piece.as_u8() + 8).of_turn(turn))
+        }
+        _ => None,
+    }
+}
+
+fn sfen_str(piece: Piece) -> String {
+    let black = piece.of_turn(Turn::Black);
+    let (promoted, letter) = match black {
+        Piece::BKing => (false, 'K'),
+        Piece::BGold => (false, 'G'),
+        Piece::BSilver => (false, 'S'),
+        Piece::BBishop => (false, 'B'),
+        Piece::BRook => (false, 'R'),
+        Piece::BPawn => (false, 'P'),
+        Piece::BSilverP => (true, 'S'),
+        Piece::BBishopP => (true, 'B'),
+        Piece::BRookP => (true, 'R'),
+        _ => (true, 'P'),
+    };
+    let letter = if piece.turn() == Turn::Black {
+        letter
+    } else {
+        letter.to_ascii_lowercase()
+    };
+    if promoted {
+        format!("+{}", letter)
+    } else {
+        letter.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_board() {
+        let sfen = "rbsgk/4p/5/P4/KGSBR b - 1";
+        assert_eq!(Board::init().to_sfen(), sfen);
+        assert_eq!(Board::from_sfen(sfen).unwrap(), Board::init());
+    }
+
+    #[test]
+    fn round_trip_with_hands_and_promoted() {
+        let mut b = board_gen::from_str("31wk,33bS,53wR,15bk,__bg,__bp,__bp,__wb").unwrap();
+        b.flip_turn();
+        let sfen = "2k2/5/+r1+S2/5/4K w G2Pb 1";
+        assert_eq!(b.to_sfen(), sfen);
+        assert_eq!(Board::from_sfen(sfen).unwrap().to_sfen(), sfen);
+    }
+
+    #[test]
+    fn invalid_sfen() {
+        let err = |s| Board::from_sfen(s).unwrap_err();
+        assert_eq!(
+            err("rbsgk/4p/5/P4 b - 1"),
+            ParseError::InvalidSfen("rbsgk/4p/5/P4 b - 1".to_string())
+        );
+        assert_eq!(
+            err("rbsgk/4p/5/P5/KGSBR b - 1"),
+            ParseError::InvalidRank("P5".to_string())
+        );
+        assert_eq!(
+            err("rbsgk/4p/5/P4/KGSBR x - 1"),
+            ParseError::InvalidTurn("x".to_string())
+        );
+        assert_eq!(
+            err("rbsgk/4p/5/P4/+KGSBR b - 1"),
+            ParseError::InvalidSfenPiece('K')
+        );
+        assert_eq!(err("rbsgk/4p/5/P4/KGSBR b 11P 1"), ParseError::TooManyHands);
+        assert_eq!(
+            err("rbsgk/4p/5/P4/KGSBR b - x"),
+            ParseError::InvalidMoveNumber("x".to_string())
+        );
+        assert_eq!(
+            err("rbsg1/4p/5/P4/KGSBR b - 1"),
+            ParseError::MissingKing(Turn::White)
+        );
+        assert_eq!(
+            err("rbsgk/4p/5/P4/KGSBR b P 1"),
+            ParseError::TooManyPieces(Piece::BPawn)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use super::{Board, Piece, Turn};
+use once_cell::sync::Lazy;
+
+/// Random keys for Zobrist hashing.
+pub struct Keys {
+    pub squares: [[u64; 25]; 22],
+    // indexed by turn, `Piece::hand_index` and the number in hands.
+    pub hands: [[[u64; 3]; 5]; 2],
+    pub white_to_move: u64,
+}
+
+impl Keys {
+    #[inline]
+    pub fn square(&self, pos: usize, piece: Piece) -> u64 {
+        self.squares[piece as usize][pos]
+    }
+
+    #[inline]
+    pub fn hand(&self, piece: Piece, count: u8) -> u64 {
+        self.hands[piece.turn_index()][piece.hand_index()][count as usize]
+    }
+}
+
+pub static KEYS: Lazy<Keys> = Lazy::new(|| {
+    // splitmix64 with a fixed seed, so that hashes are the same in every run.
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+
+    let mut keys = Keys {
+        squares: [[0; 25]; 22],
+        hands: [[[0; 3]; 5]; 2],
+        white_to_move: 0,
+    };
+    // absent squares and no pieces in hands are keyed by 0.
+    for piece_keys in keys.squares.iter_mut().skip(2) {
+        for key in piece_keys.iter_mut() {
+            *key = next();
+        }
+    }
+    for key in keys
+        .hands
+        .iter_mut()
+        .flatten()
+        .flat_map(|k| k.iter_mut().skip(1))
+    {
+        *key = next();
+    }
+    keys.white_to_move = next();
+    keys
+});
+
+/// Computes the hash of `b` from scratch.
+pub fn hash(b: &Board) -> u64 {
+    let mut hash = 0;
+    for (pos, p) in b.squares().iter().enumerate() {
+        hash ^= KEYS.square(pos, *p);
+    }
+    for p in Piece::HAND_PIECES {
+        for p in [p, p.flip()] {
+            hash ^= KEYS.hand(p, b.hand(p));
+        }
+    }
+    if b.turn == Turn::White {
+        hash ^= KEYS.white_to_move;
+    }
+    hash
+}
@@ -2,6 +2,9 @@ pub mod checkmate;
 pub mod evaluator;
 pub mod move_gen;
 pub mod searcher;
+pub mod see;
+pub mod tsume;
+pub mod tt;
 
 use super::game::*;
 use rand::prelude::RngCore;
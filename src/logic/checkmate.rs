@@ -3,7 +3,8 @@ use super::*;
 #[derive(Debug, PartialEq, Eq)]
 pub enum MateResult {
     Unknown,
-    Win,
+    // mates by the move.
+    Win(Move),
     Lose,
 }
 
@@ -13,10 +14,11 @@ pub fn is_checkmate(b: &Board) -> MateResult {
     }
     let mut next_board = b.clone();
     for m in move_gen::check_moves(b) {
-        next_board.copy_from(b);
-        next_board.put_move(&m);
-        if move_gen::is_checked(&next_board) && move_gen::evasion_moves(&next_board).is_empty() {
-            return MateResult::Win;
+        let undo = next_board.put_move(&m);
+        let mated = move_gen::evasion_moves(&next_board).is_empty();
+        next_board.undo_move(&m, undo);
+        if mated {
+            return MateResult::Win(m);
         }
     }
     MateResult::Unknown
@@ -28,13 +30,25 @@ mod tests {
 
     #[test]
     fn king_is_in_front_of_gold() {
-        let mut b = board_gen::from_str("31wk,32bg,33bp,55bk");
+        let mut b = board_gen::from_str("31wk,32bg,33bp,55bk").unwrap();
         assert_eq!(is_checkmate(b.flip_turn()), MateResult::Lose);
     }
 
     #[test]
     fn king_is_in_front_of_silver() {
-        let mut b = board_gen::from_str("31wk,32bs,33bp,55bk");
+        let mut b = board_gen::from_str("31wk,32bs,33bp,55bk").unwrap();
         assert_eq!(is_checkmate(b.flip_turn()), MateResult::Unknown);
     }
+
+    #[test]
+    fn checkmate_by_dropping_pawn_is_not_allowed() {
+        let b = board_gen::from_str("51wk,53bs,45br,15bk,__bp").unwrap();
+        assert_eq!(is_checkmate(&b), MateResult::Unknown);
+
+        let b = board_gen::from_str("51wk,53bs,45br,15bk,__bg").unwrap();
+        assert_eq!(
+            is_checkmate(&b),
+            MateResult::Win(Move::drop(&Piece::BGold, 1))
+        );
+    }
 }
@@ -7,20 +7,23 @@ pub const PIECE_VALUE: [i32; 22] = [
 
 pub fn evaluate(b: &Board) -> i32 {
     let mut sum = 0;
-    for p in b.squares {
+    for &p in b.squares() {
         sum += evaluator::PIECE_VALUE[p as usize];
     }
-    for p in b.hands {
-        sum += evaluator::PIECE_VALUE[p as usize] * 9 / 10;
+    for p in Piece::HAND_PIECES {
+        for turn in [Turn::Black, Turn::White] {
+            let p = p.of_turn(turn);
+            sum += evaluator::PIECE_VALUE[p as usize] * b.hand(p) as i32 * 9 / 10;
+        }
     }
 
     let mut distance = 0;
     let opp_king_pos = b
-        .squares
+        .squares()
         .iter()
         .position(|p| *p == Piece::BKing.of_turn(b.turn))
         .unwrap() as i32;
-    for (i, p) in b.squares.iter().enumerate() {
+    for (i, p) in b.squares().iter().enumerate() {
         if p.turn() == b.turn {
             distance += std::cmp::max(
                 (opp_king_pos / 5 - i as i32 / 5).abs(),
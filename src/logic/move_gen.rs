@@ -1,234 +1,258 @@
 use super::*;
-use once_cell::sync::Lazy;
-use std::collections::{HashMap, HashSet};
 
 pub fn all_valid_moves(board: &Board) -> Vec<Move> {
-    valid_moves(board, board.turn, true)
+    valid_moves(board, board.turn, bitboard::FULL, true)
 }
 
 pub fn taking_moves(board: &Board) -> Vec<Move> {
-    valid_moves(board, board.turn, true)
-        .into_iter()
-        .filter(|m| !board.at(m.dst as usize).is_absent())
-        .collect()
+    valid_moves(
+        board,
+        board.turn,
+        board.colors[board.turn.next() as usize],
+        false,
+    )
 }
 
+/// Returns legal moves which check the opponent's king.
 pub fn check_moves(board: &Board) -> Vec<Move> {
     let mut next_board = board.clone();
-    valid_moves(board, board.turn, true)
+    valid_moves(board, board.turn, bitboard::FULL, true)
         .into_iter()
         .filter(|m| {
-            next_board.copy_from(board);
-            next_board.put_move(m);
-            is_checked(&next_board)
+            let undo = next_board.put_move(m);
+            let checked = is_checked(&next_board);
+            next_board.undo_move(m, undo);
+            checked && !leaves_checked(&mut next_board, m)
         })
         .collect()
 }
 
 pub fn evasion_moves(board: &Board) -> Vec<Move> {
+    legal_moves(board)
+}
+
+/// Returns moves which don't leave own king checked.
+pub fn legal_moves(board: &Board) -> Vec<Move> {
     let mut next_board = board.clone();
-    valid_moves(board, board.turn, true)
+    valid_moves(board, board.turn, bitboard::FULL, true)
         .into_iter()
-        .filter(|m| {
-            next_board.copy_from(board);
-            next_board.put_move(m);
-            next_board.flip_turn();
-            !is_checked(&next_board)
-        })
+        .filter(|m| !leaves_checked(&mut next_board, m))
         .collect()
 }
 
-fn valid_moves(board: &Board, turn: Turn, include_dropping: bool) -> Vec<Move> {
+// whether the king of the side to move is checked after `m`.
+fn leaves_checked(b: &mut Board, m: &Move) -> bool {
+    let undo = b.put_move(m);
+    b.flip_turn();
+    let checked = is_checked(b);
+    b.flip_turn();
+    b.undo_move(m, undo);
+    checked
+}
+
+fn valid_moves(board: &Board, turn: Turn, targets: u32, include_dropping: bool) -> Vec<Move> {
     if board.won.is_some() {
         return vec![];
     }
     let mut moves = Vec::new();
+    let occupied = board.occupied();
+    let targets = targets & !board.colors[turn as usize];
+    let last_rank = bitboard::last_rank(turn);
 
     // move piece
-    for (pos, piece) in board.squares.iter().enumerate() {
-        if *piece == Piece::Absent || piece.turn() != turn {
-            continue;
-        }
-        for ms in &PIECE_MOVES_WITH_POSITION[&(*piece as u8, pos)] {
-            for m in ms {
-                let dst_piece = board.at(m.dst as usize);
-                if dst_piece.is_absent() {
-                    // empty. can go through
-                    moves.push(*m);
-                } else if dst_piece.turn() != turn {
-                    // occupied by opponent. have to stop.
-                    moves.push(*m);
-                    break;
+    for piece in bitboard::PIECES[turn as usize].iter() {
+        let promotable = (6..=13).contains(&piece.as_u8());
+        for src in bitboard::squares(board.pieces[*piece as usize]) {
+            let dsts = bitboard::attacks(*piece, src, occupied) & targets;
+            for dst in bitboard::squares(dsts) {
+                let (src, dst) = (src as u8, dst as u8);
+                if promotable && last_rank & bitboard::bit(dst as usize) != 0 {
+                    // with promotion
+                    moves.push(Move::new(piece, src, dst, true));
+                    // bishop, rook and pawn must promote
+                    if piece.of_turn(Turn::Black) == Piece::BSilver {
+                        moves.push(Move::new(piece, src, dst, false));
+                    }
                 } else {
-                    // occupied by same color. can't enter.
-                    break;
+                    moves.push(Move::new(piece, src, dst, false));
                 }
             }
         }
     }
 
     if include_dropping {
-        let mut generated: HashSet<Piece> = HashSet::new();
-        for (pos, piece) in board.hands.iter().enumerate() {
-            if *piece == Piece::Absent || piece.turn() != turn || generated.contains(piece) {
+        let empty = targets & !occupied;
+        let pawn = Piece::BPawn.of_turn(turn);
+        // pawn can't be dropped on the last rank, where it could never move.
+        let mut pawn_dsts = empty & !last_rank;
+        // nifu: pawn can't be dropped on the file which has own pawn.
+        for pos in bitboard::squares(board.pieces[pawn as usize]) {
+            pawn_dsts &= !bitboard::file(pos);
+        }
+
+        for piece in Piece::HAND_PIECES {
+            let piece = piece.of_turn(turn);
+            if board.hand(piece) == 0 {
                 continue;
             }
-            generated.insert(*piece);
-            for dst in 0..25 {
-                if board.at(dst) != Piece::Absent {
+            let dsts = if piece == pawn { pawn_dsts } else { empty };
+            for dst in bitboard::squares(dsts) {
+                let m = Move::drop(&piece, dst as u8);
+                if piece == pawn && is_pawn_drop_mate(board, &m) {
                     continue;
                 }
-                moves.push(Move::new(piece, (100 + pos) as u8, dst as u8, false))
+                moves.push(m)
             }
         }
     }
     moves
 }
 
+// uchifuzume: checkmate by dropping pawn is not allowed.
+fn is_pawn_drop_mate(board: &Board, m: &Move) -> bool {
+    let opp_king = Piece::BKing.of_turn(board.turn.next());
+    if bitboard::attacks(m.piece, m.dst as usize, 0) & board.pieces[opp_king as usize] == 0 {
+        return false;
+    }
+
+    let mut next_board = board.clone();
+    next_board.put_move(m);
+    // the checking pawn is next to the king, so dropping pieces never evades.
+    valid_moves(&next_board, next_board.turn, bitboard::FULL, false)
+        .iter()
+        .all(|m| leaves_checked(&mut next_board, m))
+}
+
 pub fn is_checked(b: &Board) -> bool {
-    let my_king = Piece::BKing.of_turn(b.turn);
-    match b.squares.iter().position(|p| *p == my_king) {
-        None => false, // lose
-        Some(my_king_pos) => valid_moves(b, b.turn.next(), false)
-            .iter()
-            .any(|m| m.dst == my_king_pos as u8),
+    if b.won.is_some() {
+        return false;
+    }
+    let my_king = b.pieces[Piece::BKing.of_turn(b.turn) as usize];
+    if my_king == 0 {
+        return false; // lose
     }
+    bitboard::attackers(b, my_king.trailing_zeros() as usize, b.turn.next()) != 0
 }
 
-// The followings are cache
-
-#[allow(clippy::type_complexity)]
-static PIECE_MOVES: Lazy<HashMap<u8, Vec<Vec<(i8, i8)>>>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-
-    let king = vec![
-        vec![(-1, -1)],
-        vec![(-1, 0)],
-        vec![(-1, 1)],
-        vec![(0, -1)],
-        vec![(0, 1)],
-        vec![(1, -1)],
-        vec![(1, 0)],
-        vec![(1, 1)],
-    ];
-    let gold = vec![
-        vec![(-1, -1)],
-        vec![(-1, 0)],
-        vec![(-1, 1)],
-        vec![(0, -1)],
-        vec![(0, 1)],
-        vec![(1, 0)],
-    ];
-    let silver = vec![
-        vec![(-1, -1)],
-        vec![(-1, 0)],
-        vec![(-1, 1)],
-        vec![(1, -1)],
-        vec![(1, 1)],
-    ];
-    let bishop = vec![
-        vec![(-1, -1), (-2, -2), (-3, -3), (-4, -4)],
-        vec![(-1, 1), (-2, 2), (-3, 3), (-4, 4)],
-        vec![(1, -1), (2, -2), (3, -3), (4, -4)],
-        vec![(1, 1), (2, 2), (3, 3), (4, 4)],
-    ];
-    let bishop_p = vec![
-        vec![(-1, -1), (-2, -2), (-3, -3), (-4, -4)],
-        vec![(-1, 1), (-2, 2), (-3, 3), (-4, 4)],
-        vec![(1, -1), (2, -2), (3, -3), (4, -4)],
-        vec![(1, 1), (2, 2), (3, 3), (4, 4)],
-        vec![(-1, 0)],
-        vec![(0, -1)],
-        vec![(0, 1)],
-        vec![(1, 0)],
-        vec![(1, 1)],
-    ];
-    let rook = vec![
-        vec![(-1, 0), (-2, 0), (-3, 0), (-4, 0)],
-        vec![(0, -1), (0, -2), (0, -3), (0, -4)],
-        vec![(0, 1), (0, 2), (0, 3), (0, 4)],
-        vec![(1, 0), (2, 0), (3, 0), (4, 0)],
-    ];
-    let rook_p = vec![
-        vec![(-1, 0), (-2, 0), (-3, 0), (-4, 0)],
-        vec![(0, -1), (0, -2), (0, -3), (0, -4)],
-        vec![(0, 1), (0, 2), (0, 3), (0, 4)],
-        vec![(1, 0), (2, 0), (3, 0), (4, 0)],
-        vec![(-1, -1)],
-        vec![(-1, 1)],
-        vec![(1, -1)],
-        vec![(1, 1)],
-    ];
-    let pawn = vec![vec![(-1, 0)]];
-
-    for i in 2..=21 {
-        let piece = Piece::from_u8(i);
-        let piece_of_black = piece.of_turn(Turn::Black);
-
-        let mut v = match piece_of_black {
-            Piece::BKing => king.clone(),
-            Piece::BGold => gold.clone(),
-            Piece::BSilver => silver.clone(),
-            Piece::BBishop => bishop.clone(),
-            Piece::BRook => rook.clone(),
-            Piece::BPawn => pawn.clone(),
-            Piece::BSilverP => gold.clone(),
-            Piece::BBishopP => bishop_p.clone(),
-            Piece::BRookP => rook_p.clone(),
-            Piece::BPawnP => gold.clone(),
-            _ => vec![],
-        };
-
-        if piece.turn() == Turn::White {
-            v = v
-                .iter()
-                .map(|v2| v2.iter().map(|l| (-l.0, l.1)).collect())
-                .collect();
-        }
+/// Counts leaf nodes of legal moves up to `depth`.
+pub fn perft(b: &mut Board, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = legal_moves(b);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for m in moves {
+        let undo = b.put_move(&m);
+        nodes += perft(b, depth - 1);
+        b.undo_move(&m, undo);
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        map.insert(piece.as_u8(), v);
-    }
-    map
-});
-
-#[allow(clippy::type_complexity)]
-static PIECE_MOVES_WITH_POSITION: Lazy<HashMap<(u8, usize), Vec<Vec<Move>>>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-    for pos in 0..=24 {
-        let y = (pos / 5) as i8;
-        let x = (pos % 5) as i8;
-        for (piece, moves) in PIECE_MOVES.iter() {
-            let v = moves
-                .iter()
-                .map(|vs| {
-                    vs.iter()
-                        .filter(|(dy, dx)| y + dy >= 0 && y + dy <= 4 && x + dx >= 0 && x + dx <= 4)
-                        .flat_map(|(dy, dx)| {
-                            let p = Piece::from_u8(*piece);
-                            let src = (y * 5 + x) as u8;
-                            let dst = ((y + dy) * 5 + x + dx) as u8;
-                            if ((y + dy == 0 && piece % 2 == 0) || (y + dy == 4 && piece % 2 == 1))
-                                && (6 <= *piece && *piece <= 13)
-                            {
-                                // with promotion
-                                if p.of_turn(Turn::Black) == Piece::BSilver {
-                                    vec![
-                                        Move::new(&p, src, dst, true),
-                                        Move::new(&p, src, dst, false),
-                                    ]
-                                } else {
-                                    // bishop, rook and pawn must promote
-                                    vec![Move::new(&p, src, dst, true)]
-                                }
-                            } else {
-                                vec![Move::new(&p, src, dst, false)]
-                            }
-                        })
-                        .collect()
-                })
-                .collect();
-            map.insert((*piece, pos), v);
+    fn drops_of(b: &Board, piece: Piece) -> Vec<u8> {
+        all_valid_moves(b)
+            .iter()
+            .filter(|m| m.is_drop() && m.piece == piece)
+            .map(|m| m.dst)
+            .collect()
+    }
+
+    #[test]
+    fn nifu() {
+        let b = board_gen::from_str("51wk,34bp,55bk,__bp").unwrap();
+        let drops = drops_of(&b, Piece::BPawn);
+        assert!(!drops.is_empty());
+        assert!(drops.iter().all(|dst| dst % 5 != 2));
+    }
+
+    #[test]
+    fn opponent_pawn_is_not_nifu() {
+        let b = board_gen::from_str("51wk,12wp,55bk,__bp").unwrap();
+        assert!(drops_of(&b, Piece::BPawn).contains(&14));
+    }
+
+    #[test]
+    fn promoted_pawn_is_not_nifu() {
+        let b = board_gen::from_str("51wk,34bP,55bk,__bp").unwrap();
+        assert!(drops_of(&b, Piece::BPawn).contains(&7));
+    }
+
+    #[test]
+    fn pawn_is_not_dropped_on_last_rank() {
+        let b = board_gen::from_str("51wk,55bk,__bp,__wp").unwrap();
+        let drops = drops_of(&b, Piece::BPawn);
+        assert_eq!(drops.len(), 19);
+        assert!(drops.iter().all(|dst| dst / 5 != 0));
+
+        let mut b = b;
+        b.flip_turn();
+        let drops = drops_of(&b, Piece::WPawn);
+        assert_eq!(drops.len(), 19);
+        assert!(drops.iter().all(|dst| dst / 5 != 4));
+    }
+
+    #[test]
+    fn other_pieces_can_be_dropped_on_last_rank() {
+        let b = board_gen::from_str("51wk,55bk,__bg").unwrap();
+        assert!(drops_of(&b, Piece::BGold).contains(&2));
+    }
+
+    #[test]
+    fn no_unpromoted_piece_is_left_without_moves() {
+        // pawn, bishop and rook must promote on the last rank, silver may not.
+        let b = board_gen::from_str("51wk,22bp,33bb,13br,44bs,55bk").unwrap();
+        for m in all_valid_moves(&b) {
+            if m.dst / 5 != 0 {
+                assert!(!m.promote, "{:?}", m);
+            } else if m.piece == Piece::BSilver {
+                assert!(all_valid_moves(&b).contains(&Move {
+                    promote: !m.promote,
+                    ..m
+                }));
+            } else if m.piece != Piece::BKing {
+                assert!(m.promote, "{:?}", m);
+            }
         }
     }
-    map
-});
+
+    #[test]
+    fn uchifuzume() {
+        // dropping pawn on 5b is checkmate.
+        let b = board_gen::from_str("51wk,53bs,45br,15bk,__bp").unwrap();
+        assert!(!drops_of(&b, Piece::BPawn).contains(&5));
+
+        // the king can escape to 4a.
+        let b = board_gen::from_str("51wk,53bs,15bk,__bp").unwrap();
+        assert!(drops_of(&b, Piece::BPawn).contains(&5));
+    }
+
+    #[test]
+    fn legal_moves_dont_leave_king_checked() {
+        // silver on 5b is pinned by rook, and king can't go to 4b.
+        let b = board_gen::from_str("51wk,52ws,54br,43bg,15bk,w").unwrap();
+        let moves = legal_moves(&b);
+        assert!(all_valid_moves(&b).len() > moves.len());
+        let silver_moves: Vec<&Move> = moves.iter().filter(|m| m.piece == Piece::WSilver).collect();
+        assert_eq!(
+            silver_moves,
+            vec![&Move::new(&Piece::WSilver, 5, 10, false)]
+        );
+        assert!(!moves.contains(&Move::new(&Piece::WKing, 0, 6, false)));
+        assert!(moves.contains(&Move::new(&Piece::WKing, 0, 1, false)));
+    }
+
+    #[test]
+    fn perft_from_initial_board() {
+        let mut b = Board::init();
+        let nodes: Vec<u64> = (1..=4).map(|depth| perft(&mut b, depth)).collect();
+        assert_eq!(nodes, vec![14, 181, 2484, 34663]);
+        assert_eq!(b, Board::init());
+    }
+}
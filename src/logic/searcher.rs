@@ -1,164 +1,739 @@
 use super::checkmate::MateResult;
+use super::tsume::TsumeResult;
+use super::tt::{Bound, Entry, TranspositionTable};
 use super::*;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct SearchResult {
     pub m: Move,
+    // principal variation, which starts with `m`.
+    pub pv: Vec<Move>,
     pub score: i32,
     pub searched: i32,
+    pub depth: u8,
 }
 
-const SEARCH_DEPTH: u8 = 4;
-const QSEARCH_EPTH: u8 = 3;
-const SCORE_LIMIT: i32 = 100_000_000;
-const WIN_THRESH: i32 = 90_000_000;
+pub const SCORE_LIMIT: i32 = 100_000_000;
+pub const WIN_THRESH: i32 = 90_000_000;
 
-pub fn find_best_move(b: &Board) -> Option<SearchResult> {
-    let mut last_result = search_result_of(0, 0);
-    for depth in 1..=SEARCH_DEPTH {
-        last_result = rec_search(b, 0, depth, -SCORE_LIMIT, SCORE_LIMIT, false);
-        if last_result.m == invalid_move() {
+/// Score of the side to move, which mates the opponent at `ply` from the root.
+pub fn mate_score(ply: u8) -> i32 {
+    SCORE_LIMIT - ply as i32
+}
+
+/// Score of the side to move, which is mated at `ply` from the root.
+pub fn mated_score(ply: u8) -> i32 {
+    -mate_score(ply)
+}
+
+/// Plies from the root to the mate, which is negative when the side to move at
+/// the root is mated. `None` for a score which is not a mate.
+pub fn mate_distance(score: i32) -> Option<i32> {
+    if score > WIN_THRESH {
+        Some(SCORE_LIMIT - score)
+    } else if score < -WIN_THRESH {
+        Some(-SCORE_LIMIT - score)
+    } else {
+        None
+    }
+}
+
+/// Parameters which decide how the search plays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchConfig {
+    // iterative deepening stops at this depth.
+    pub depth: u8,
+    // how deep taking moves are searched after `depth`.
+    pub qdepth: u8,
+    // whether moves of the same priority are ordered randomly.
+    pub random: bool,
+    // seed of the random ordering, or from entropy if `None`.
+    // the same seed plays the same move in the same position.
+    pub seed: Option<u64>,
+    // the history table is halved when a value exceeds this.
+    pub history_max: i32,
+    // number of threads which search in parallel.
+    pub threads: usize,
+    // whether moves after the first are searched with null windows first.
+    pub pvs: bool,
+    // half width of the window around the last score in iterative deepening.
+    // 0 searches with the full window.
+    pub aspiration_window: i32,
+    // whether a node is cut when passing still fails high with a shallower search.
+    pub null_move: bool,
+    // whether late quiet moves are searched shallower first.
+    pub lmr: bool,
+    // positions which the tsume solver visits before the search. 0 disables it.
+    pub tsume_nodes: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            depth: 4,
+            qdepth: 3,
+            random: true,
+            seed: None,
+            history_max: 1000,
+            threads: 1,
+            pvs: true,
+            aspiration_window: 100,
+            null_move: true,
+            lmr: true,
+            tsume_nodes: 10_000,
+        }
+    }
+}
+
+impl SearchConfig {
+    pub const MAX_DEPTH: u8 = 64;
+    pub const MAX_THREADS: usize = 256;
+}
+
+/// Limits of a search, which stops at whichever comes first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // remaining time of the side to move, and what is given for the move.
+    pub time: Option<Duration>,
+    pub increment: Duration,
+    pub byoyomi: Duration,
+    // the result is held until `stop` is set, even if the search ends earlier.
+    pub infinite: bool,
+}
+
+impl Limits {
+    /// Returns how long the search can take, or `None` if it has no time limit.
+    pub fn allocate_time(&self) -> Option<Duration> {
+        if self.movetime.is_some() {
+            return self.movetime;
+        }
+        if self.time.is_none() && self.byoyomi.is_zero() {
             return None;
-        } else if last_result.score > WIN_THRESH {
-            return Some(last_result);
         }
+        let time = self.time.unwrap_or_default();
+        // keep a margin for the communication with the GUI.
+        let margin = Duration::from_millis(50);
+        let max = (time + self.byoyomi).saturating_sub(margin);
+        let allotted = time / 20 + self.increment + self.byoyomi;
+        Some(allotted.saturating_sub(margin).min(max))
     }
-    if last_result.m == invalid_move() {
-        None
-    } else {
-        Some(last_result)
+}
+
+/// Searches the best move to `config.depth`, reusing and filling `tt`.
+pub fn find_best_move(
+    b: &Board,
+    tt: &TranspositionTable,
+    config: &SearchConfig,
+) -> Option<SearchResult> {
+    let stop = AtomicBool::new(false);
+    search(b, tt, config, &Limits::default(), &stop, &|_| {})
+}
+
+/// Searches the best move deeper and deeper until `limits` or `stop` is set.
+///
+/// An aborted iteration is discarded and the last completed one is returned.
+/// The first iteration is always completed to have a move to play.
+/// `report` is called with the result of each completed iteration.
+///
+/// With `config.threads > 1`, helper threads search the same position sharing
+/// `tt` (Lazy SMP), and the deepest completed result among threads is returned.
+///
+/// A mate proven by the tsume solver in `config.tsume_nodes` is played without searching.
+/// With `limits.infinite`, the result is returned only after `stop` is set.
+pub fn search(
+    b: &Board,
+    tt: &TranspositionTable,
+    config: &SearchConfig,
+    limits: &Limits,
+    stop: &AtomicBool,
+    report: &dyn Fn(&SearchResult),
+) -> Option<SearchResult> {
+    // a mate is suspected while the side to move can check, and the solver
+    // gives up at once otherwise.
+    if config.tsume_nodes > 0 {
+        match tsume::solve(b, config.tsume_nodes) {
+            // an empty line has no move to play, and is left to the search.
+            (TsumeResult::Mate(moves), nodes) if !moves.is_empty() => {
+                let result = SearchResult {
+                    m: moves[0],
+                    score: mate_score(moves.len() as u8),
+                    searched: nodes as i32,
+                    depth: moves.len() as u8,
+                    pv: moves,
+                };
+                report(&result);
+                wait_for_stop(limits, stop);
+                return Some(result);
+            }
+            _ => {}
+        }
+    }
+
+    let start = Instant::now();
+    // helpers are stopped when the main thread finishes.
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let helpers: Vec<_> = (1..config.threads)
+            .map(|id| {
+                let done = &done;
+                scope.spawn(move || {
+                    let mut s = Search::new(b, tt, config, limits, [stop, done], start, id);
+                    s.abortable = true;
+                    // half of helpers skip a depth to search other positions first.
+                    iterate(b, &mut s, 1 + (id % 2) as u8, &|_| {})
+                })
+            })
+            .collect();
+
+        let mut s = Search::new(b, tt, config, limits, [stop, &done], start, 0);
+        let mut result = iterate(b, &mut s, 1, report);
+        done.store(true, Ordering::Relaxed);
+        for helper in helpers {
+            let helper_result = helper.join().unwrap();
+            if let (Some(main), Some(helper)) = (&mut result, helper_result) {
+                main.searched += helper.searched;
+                if helper.depth > main.depth {
+                    *main = SearchResult {
+                        searched: main.searched,
+                        ..helper
+                    };
+                    report(main);
+                }
+            }
+        }
+        wait_for_stop(limits, stop);
+        result
+    })
+}
+
+// waits for `stop` under `go infinite`, which ends with it.
+fn wait_for_stop(limits: &Limits, stop: &AtomicBool) {
+    while limits.infinite && !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// iterative deepening from `first_depth` to `config.depth`.
+fn iterate(
+    b: &Board,
+    s: &mut Search,
+    first_depth: u8,
+    report: &dyn Fn(&SearchResult),
+) -> Option<SearchResult> {
+    let mut b = b.clone();
+    let mut last_result = None;
+    for depth in first_depth..=s.config.depth {
+        let mut delta = s.config.aspiration_window;
+        let last_score = last_result.as_ref().map_or(0, |r: &SearchResult| r.score);
+        let (mut alpha, mut beta) = if delta > 0 && last_score.abs() < WIN_THRESH {
+            (last_score - delta, last_score + delta)
+        } else {
+            (-SCORE_LIMIT, SCORE_LIMIT)
+        };
+        s.max_extended_depth = depth * 2;
+        let mut result = loop {
+            let result = rec_search(&mut b, s, 0, depth, alpha, beta, false);
+            // search again with the wider window if the score is out of it.
+            let fail_low = result.score <= alpha && alpha > -SCORE_LIMIT;
+            let fail_high = result.score >= beta && beta < SCORE_LIMIT;
+            if s.aborted || !(fail_low || fail_high) {
+                break result;
+            }
+            delta = delta.saturating_mul(8);
+            // the score will be far away, such as a mate.
+            let full = delta > 5000;
+            if fail_low {
+                alpha = if full {
+                    -SCORE_LIMIT
+                } else {
+                    last_score - delta
+                };
+            } else {
+                beta = if full {
+                    SCORE_LIMIT
+                } else {
+                    last_score + delta
+                };
+            }
+        };
+        if s.aborted {
+            break;
+        }
+        s.abortable = true;
+        result.depth = depth;
+        if result.m == invalid_move() {
+            return None;
+        }
+        let won = result.score > WIN_THRESH;
+        report(&result);
+        last_result = Some(result);
+        // the next iteration would not finish in the rest of time.
+        if won || s.allotted.is_some_and(|t| s.start.elapsed() * 2 > t) {
+            break;
+        }
+    }
+    last_result
+}
+
+// state shared in a search of a thread.
+struct Search<'a> {
+    tt: &'a TranspositionTable,
+    config: &'a SearchConfig,
+    rng: Option<StdRng>,
+    order: Box<MoveOrder>,
+    // the search is aborted if either is set.
+    stops: [&'a AtomicBool; 2],
+    start: Instant,
+    allotted: Option<Duration>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    // null move is made only once in a line.
+    in_null_move: bool,
+    // checking moves are extended until lines get this long.
+    max_extended_depth: u8,
+    // repetitions found in the tree, which make results depend on the path.
+    repetitions: u64,
+    abortable: bool,
+    aborted: bool,
+}
+
+impl Search<'_> {
+    fn new<'a>(
+        b: &Board,
+        tt: &'a TranspositionTable,
+        config: &'a SearchConfig,
+        limits: &Limits,
+        stops: [&'a AtomicBool; 2],
+        start: Instant,
+        id: usize,
+    ) -> Search<'a> {
+        let rng = match config.seed {
+            _ if !config.random => None,
+            // mixed with the position to vary the noise among moves in a game.
+            Some(seed) => Some(StdRng::seed_from_u64(seed ^ b.hash ^ id as u64)),
+            None => Some(StdRng::from_entropy()),
+        };
+        Search {
+            tt,
+            config,
+            rng,
+            order: Box::new(MoveOrder::new()),
+            stops,
+            start,
+            allotted: limits.allocate_time(),
+            // each thread counts its own nodes, so the budget is divided among them.
+            max_nodes: limits.nodes.map(|n| n.div_ceil(config.threads as u64)),
+            nodes: 0,
+            in_null_move: false,
+            max_extended_depth: 0,
+            repetitions: 0,
+            abortable: false,
+            aborted: false,
+        }
+    }
+
+    // counts a node and returns whether the search should be aborted.
+    fn abort(&mut self) -> bool {
+        self.nodes += 1;
+        if self.abortable && !self.aborted {
+            // looking at the clock is slower than the others.
+            self.aborted = self.stops.iter().any(|stop| stop.load(Ordering::Relaxed))
+                || self.max_nodes.is_some_and(|n| self.nodes > n)
+                || (self.nodes.is_multiple_of(256)
+                    && self.allotted.is_some_and(|t| self.start.elapsed() >= t));
+        }
+        self.aborted
     }
 }
 
 // if rec_search finds `score > beta`, the result will be discarded by alpha-beta.
 fn rec_search(
-    b: &Board,
+    b: &mut Board,
+    s: &mut Search,
     depth: u8,
     max_depth: u8,
     alpha: i32,
     beta: i32,
     need_qsearch: bool,
 ) -> SearchResult {
-    if b.won.is_some() {
-        // always lose on this case.
-        return search_result_of(-SCORE_LIMIT + (depth as i32), 1);
+    if s.abort() {
+        return search_result_of(0, 0);
+    }
+    if let Some(result) = finished_result(b, s, depth) {
+        return result;
     }
     if depth >= max_depth {
-        // do q search
-        let qsearch_depth = if need_qsearch {
-            depth + QSEARCH_EPTH
+        // do q search, where the king in check can't stay as it is.
+        let in_check = move_gen::is_checked(b);
+        let qsearch_depth = if need_qsearch || in_check {
+            depth + s.config.qdepth
         } else {
             depth
         };
-        return q_rec_search(b, depth, qsearch_depth, evaluator::evaluate(b), beta);
+        let stand_pat = if in_check {
+            alpha
+        } else {
+            evaluator::evaluate(b)
+        };
+        return q_rec_search(b, s, depth, qsearch_depth, stand_pat, beta);
     }
-    let moves = move_gen::all_valid_moves(b);
-
-    let moves = reorder_moves(b, &moves);
-    let mut best: SearchResult = search_result_of(alpha, 0);
 
-    let mut next_board = b.clone();
-    for m in moves {
-        next_board.copy_from(b);
-        next_board.put_move(&m);
+    let remaining = max_depth - depth;
+    let entry = s.tt.probe(b.hash);
+    if let Some(e) = entry {
+        // the root always searches to return a legal move.
+        let score = tt::score_from_tt(e.score, depth);
+        let cut = match e.bound {
+            Bound::Exact => true,
+            Bound::Lower => score >= beta,
+            Bound::Upper => score <= alpha,
+        };
+        if depth > 0 && e.depth >= remaining && cut {
+            // the pv is cut here, as only the first move is stored.
+            let mut result = search_result_of(score, 1);
+            result.m = e.m;
+            return result;
+        }
+    }
 
-        let is_taking_move = !b.squares[m.dst as usize].is_absent();
+    let repetitions = s.repetitions;
+    let in_check = move_gen::is_checked(b);
+    if s.config.null_move
+        && depth > 0
+        && remaining > NULL_MOVE_REDUCTION
+        && !in_check
+        && !s.in_null_move
+        && beta.abs() < WIN_THRESH
+        && !is_zugzwang_prone(b)
+    {
+        // if passing is still good enough, a move must be better.
+        let null_undo = b.put_null_move();
+        s.in_null_move = true;
         let result = rec_search(
-            &next_board,
+            b,
+            s,
             depth + 1,
-            max_depth,
+            max_depth - NULL_MOVE_REDUCTION,
             -beta,
-            -best.score,
-            is_taking_move,
+            -beta + 1,
+            false,
         );
+        s.in_null_move = false;
+        b.undo_null_move(null_undo);
+        if s.aborted {
+            return search_result_of(0, 0);
+        }
+        if -result.score >= beta {
+            return search_result_of(beta, result.searched);
+        }
+    }
+
+    // the move to play must be legal, though taking king is enough inside the tree.
+    // the king in check should escape, not to be taken after extended plies.
+    let moves = if depth == 0 || in_check {
+        move_gen::evasion_moves(b)
+    } else {
+        move_gen::all_valid_moves(b)
+    };
+    if depth > 0 && in_check && moves.is_empty() {
+        return search_result_of(mated_score(depth), 1);
+    }
+
+    let mut moves = reorder_moves(b, &moves, &s.order, depth, s.rng.as_mut());
+    // the best move of the previous search is likely to be the best again.
+    if let Some(i) = entry.and_then(|e| moves.iter().position(|m| *m == e.m)) {
+        let m = moves.remove(i);
+        moves.insert(0, m);
+    }
+    let mut best: SearchResult = search_result_of(alpha, 0);
+
+    for (i, m) in moves.into_iter().enumerate() {
+        let is_taking_move = !b.at(m.dst as usize).is_absent();
+        let undo = b.put_move(&m);
+        let gives_check = move_gen::is_checked(b);
+        // checks are searched deeper, as they lead to mates.
+        let max_depth = if gives_check && max_depth < s.max_extended_depth {
+            max_depth + 1
+        } else {
+            max_depth
+        };
+        // late quiet moves are unlikely to be the best, thanks to the ordering.
+        let reduction = (s.config.lmr
+            && i >= LMR_MOVES
+            && remaining > LMR_REDUCTION
+            && !is_taking_move
+            && !m.promote
+            && !in_check
+            && !gives_check) as u8
+            * LMR_REDUCTION;
+        let mut result;
+        if i == 0 || (!s.config.pvs && reduction == 0) {
+            result = rec_search(
+                b,
+                s,
+                depth + 1,
+                max_depth,
+                -beta,
+                -best.score,
+                is_taking_move,
+            );
+        } else {
+            // prove that the move is not better than the best with a null window.
+            let bound = -best.score;
+            let reduced = max_depth - reduction;
+            result = rec_search(b, s, depth + 1, reduced, bound - 1, bound, is_taking_move);
+            if reduction > 0 && !s.aborted && -result.score > best.score {
+                best.searched += result.searched;
+                result = rec_search(b, s, depth + 1, max_depth, bound - 1, bound, is_taking_move);
+            }
+            let score = -result.score;
+            if !s.aborted && score > best.score && score < beta {
+                best.searched += result.searched;
+                result = rec_search(b, s, depth + 1, max_depth, -beta, bound, is_taking_move);
+            }
+        }
+        b.undo_move(&m, undo);
+        if s.aborted {
+            // the result is not reliable, and must not be stored.
+            return best;
+        }
         let score = -result.score;
         best.searched += result.searched;
         if score > best.score {
             best.m = m;
             best.score = score;
+            best.pv = pv_of(m, result.pv);
         }
-        if score >= beta || score > WIN_THRESH {
+        // no move can mate sooner.
+        if score >= beta || score >= mate_score(depth + 1) {
+            if !is_taking_move {
+                s.order.add_killer(depth, m);
+            }
+            s.order.add_history(&m, remaining, s.config.history_max);
+            let remaining = stored_depth(s, repetitions, remaining);
+            store(s.tt, b, depth, remaining, Bound::Lower, &best);
             return best;
         }
     }
 
-    update_move_order_table(&best.m);
+    if best.m != invalid_move() {
+        s.order
+            .add_history(&best.m, remaining, s.config.history_max);
+    }
+    let bound = if best.score > alpha {
+        Bound::Exact
+    } else {
+        Bound::Upper
+    };
+    let remaining = stored_depth(s, repetitions, remaining);
+    store(s.tt, b, depth, remaining, bound, &best);
     best
 }
 
-fn q_rec_search(b: &Board, depth: u8, max_depth: u8, alpha: i32, beta: i32) -> SearchResult {
-    if b.won.is_some() {
-        // always lose on this case.
-        return search_result_of(-SCORE_LIMIT + (depth as i32), 1);
+// a result which depends on a repetition in the line is wrong in other searches,
+// so it is stored without depth to keep only the move for the ordering.
+fn stored_depth(s: &Search, repetitions: u64, remaining: u8) -> u8 {
+    if s.repetitions == repetitions {
+        remaining
+    } else {
+        0
+    }
+}
+
+const NULL_MOVE_REDUCTION: u8 = 2;
+const LMR_MOVES: usize = 3;
+const LMR_REDUCTION: u8 = 1;
+
+// passing can be better than any move, only when there are few pieces to move.
+fn is_zugzwang_prone(b: &Board) -> bool {
+    let turn = b.turn as usize;
+    let hands: u8 = b.hands[turn].iter().sum();
+    let others = b.pieces[Piece::BKing.of_turn(b.turn) as usize]
+        | b.pieces[Piece::BPawn.of_turn(b.turn) as usize];
+    hands == 0 && b.colors[turn] & !others == 0
+}
+
+fn store(
+    tt: &TranspositionTable,
+    b: &Board,
+    depth: u8,
+    remaining: u8,
+    bound: Bound,
+    result: &SearchResult,
+) {
+    tt.store(Entry {
+        hash: b.hash,
+        depth: remaining,
+        bound,
+        score: tt::score_to_tt(result.score, depth),
+        m: result.m,
+    });
+}
+
+fn q_rec_search(
+    b: &mut Board,
+    s: &mut Search,
+    depth: u8,
+    max_depth: u8,
+    alpha: i32,
+    beta: i32,
+) -> SearchResult {
+    if s.abort() {
+        return search_result_of(0, 0);
+    }
+    if let Some(result) = finished_result(b, s, depth) {
+        return result;
     }
     if depth >= max_depth {
         // evaluate because max_depth
         return evaluate_leaf(b, depth);
     }
-    let moves = move_gen::taking_moves(b);
+    let moves: Vec<Move> = if move_gen::is_checked(b) {
+        let moves = move_gen::evasion_moves(b);
+        if moves.is_empty() {
+            return search_result_of(mated_score(depth), 1);
+        }
+        moves
+    } else {
+        // losing captures hardly change the evaluation.
+        move_gen::taking_moves(b)
+            .into_iter()
+            .filter(|m| see::see(b, m) >= 0)
+            .collect()
+    };
     if moves.is_empty() {
         // evaluate because its quiescence
         return evaluate_leaf(b, depth);
     }
 
-    let moves = reorder_moves(b, &moves);
+    let moves = reorder_moves(b, &moves, &s.order, depth, s.rng.as_mut());
     let mut best: SearchResult = search_result_of(alpha, 0);
 
-    let mut next_board = b.clone();
     for m in moves {
-        next_board.copy_from(b);
-        next_board.put_move(&m);
-
-        let result = q_rec_search(&next_board, depth + 1, max_depth, -beta, -best.score);
+        let undo = b.put_move(&m);
+        let result = q_rec_search(b, s, depth + 1, max_depth, -beta, -best.score);
+        b.undo_move(&m, undo);
+        if s.aborted {
+            return best;
+        }
         let score = -result.score;
         best.searched += result.searched;
         if score > best.score {
             best.m = m;
             best.score = score;
+            best.pv = pv_of(m, result.pv);
         }
-        if score > beta || score > WIN_THRESH {
+        if score > beta || score >= mate_score(depth + 1) {
             return best;
         }
     }
     best
 }
 
+// the game is finished by taking king or sennichite.
+// a repetition in the search tree is regarded as sennichite.
+fn finished_result(b: &Board, s: &mut Search, depth: u8) -> Option<SearchResult> {
+    let (winner, ply) = match b.won {
+        // the king was left to be taken 2 plies ago, as if it was mated there.
+        // taking it at the root is a mate in 1.
+        Some(winner) => (winner, depth.saturating_sub(2).max(1)),
+        None if depth > 0 && b.repetition_count() >= 2 => {
+            s.repetitions += 1;
+            (b.sennichite_winner(), depth)
+        }
+        None => return None,
+    };
+    if winner == b.turn {
+        Some(search_result_of(mate_score(ply), 1))
+    } else {
+        Some(search_result_of(mated_score(ply), 1))
+    }
+}
+
 fn evaluate_leaf(b: &Board, depth: u8) -> SearchResult {
     match checkmate::is_checkmate(b) {
         MateResult::Unknown => search_result_of(evaluator::evaluate(b), 1),
-        MateResult::Win => search_result_of(SCORE_LIMIT - (depth as i32), 1),
-        MateResult::Lose => search_result_of(-SCORE_LIMIT + (depth as i32), 1),
+        MateResult::Win(m) => {
+            let mut result = search_result_of(mate_score(depth + 1), 1);
+            result.m = m;
+            result.pv = vec![m];
+            result
+        }
+        MateResult::Lose => search_result_of(mated_score(depth), 1),
     }
 }
 
-fn update_move_order_table(m: &Move) {
-    let mut table = MOVE_ORDER_TABLE.lock().unwrap();
-    let value = table.entry(MoveOrderingKey(m.piece, m.dst)).or_insert(0);
-    *value += 1;
-    if *value > MOVE_ORDER_TABLE_MAX {
-        for (_, val) in table.iter_mut() {
-            *val /= 2;
+// statistics of good moves in a search.
+struct MoveOrder {
+    // quiet moves which caused beta cut, per ply.
+    killers: [[Move; 2]; 256],
+    // how often a move was the best, indexed by piece and destination.
+    history: [[i32; 25]; 22],
+}
+
+impl MoveOrder {
+    fn new() -> MoveOrder {
+        MoveOrder {
+            killers: [[invalid_move(); 2]; 256],
+            history: [[0; 25]; 22],
+        }
+    }
+
+    fn add_killer(&mut self, ply: u8, m: Move) {
+        let killers = &mut self.killers[ply as usize];
+        if killers[0] != m {
+            killers[1] = killers[0];
+            killers[0] = m;
         }
     }
+
+    // deeper searches are more reliable.
+    fn add_history(&mut self, m: &Move, remaining: u8, max: i32) {
+        let value = &mut self.history[m.piece as usize][m.dst as usize];
+        *value += remaining as i32 * remaining as i32;
+        if *value > max {
+            for v in self.history.iter_mut().flatten() {
+                *v /= 2;
+            }
+        }
+    }
+
+    fn is_killer(&self, ply: u8, m: &Move) -> bool {
+        self.killers[ply as usize].contains(m)
+    }
 }
 
 // re-order moves for alpha-beta cut
-fn reorder_moves(b: &Board, moves: &[Move]) -> Vec<Move> {
+fn reorder_moves(
+    b: &Board,
+    moves: &[Move],
+    order: &MoveOrder,
+    ply: u8,
+    mut rng: Option<&mut StdRng>,
+) -> Vec<Move> {
     let mut power_of_moves: [u8; 25] = [0; 25];
     for m in moves {
-        if m.src < 100 {
+        if !m.is_drop() {
             power_of_moves[m.dst as usize] += 1;
         }
     }
-    let table = MOVE_ORDER_TABLE.lock().unwrap();
     let mut tupls: Vec<(Move, i32)> = moves
         .iter()
-        .map(|m| (*m, move_priority(b, m, &power_of_moves, &table)))
+        .map(|m| {
+            // randomize order (< 3000)
+            let noise = rng
+                .as_mut()
+                .map_or(0, |rng| (rng.next_u32() / 10) as i32 % 3000);
+            (*m, move_priority(b, m, &power_of_moves, order, ply) + noise)
+        })
         .collect();
-    tupls.sort_by(|a, b| b.1.cmp(&a.1));
+    tupls.sort_by_key(|t| std::cmp::Reverse(t.1));
     tupls.iter().map(|t| t.0).collect()
 }
 
@@ -166,19 +741,29 @@ fn move_priority(
     b: &Board,
     m: &Move,
     power_of_moves: &[u8; 25],
-    statistics: &HashMap<MoveOrderingKey, MoveOrderingValue>,
+    order: &MoveOrder,
+    ply: u8,
 ) -> i32 {
     let mut priority = 0;
     let scale = 100;
 
-    // piece taking move
+    // piece taking move, by the material it wins after the exchange
     let dst_piece = b.at(m.dst as usize);
     if dst_piece != Piece::Absent {
-        priority += (5000 + evaluator::PIECE_VALUE[dst_piece as usize].abs()) * scale;
+        let gain = see::see(b, m);
+        if gain >= 0 {
+            priority += (5000 + gain) * scale;
+        } else {
+            // losing one follows killers.
+            priority += 1000 * scale + gain;
+        }
+    } else if order.is_killer(ply, m) {
+        // quiet move which was good in the sibling
+        priority += 2000 * scale;
     }
 
     // moving precedes putting
-    if m.src < 100 {
+    if !m.is_drop() {
         priority += 100 * scale;
     } else if power_of_moves[m.dst as usize] == 0 {
         // free piece is bad.
@@ -190,20 +775,15 @@ fn move_priority(
 
     // near enemy's king is better (< 4000)
     let enemy_king_pos = b
-        .squares
+        .squares()
         .iter()
         .position(|p| *p == Piece::BKing.of_turn(b.turn.next()))
         .unwrap();
     priority += (4 - ((enemy_king_pos as i32 / 5) - (m.dst as i32 / 5)).abs()) * 500;
     priority += (4 - ((enemy_king_pos as i32 % 5) - (m.dst as i32 % 5)).abs()) * 500;
 
-    // order by statistics (< 1000)
-    priority += *statistics
-        .get(&MoveOrderingKey(m.piece, m.dst))
-        .unwrap_or(&0);
-
-    // randomize order (< 3000)
-    priority += (rand::thread_rng().next_u32() / 10) as i32 % 3000;
+    // order by history (< 1000)
+    priority += order.history[m.piece as usize][m.dst as usize];
 
     priority
 }
@@ -212,24 +792,25 @@ fn invalid_move() -> Move {
     Move::new(&Piece::Absent, 0, 0, false)
 }
 
+fn pv_of(m: Move, mut rest: Vec<Move>) -> Vec<Move> {
+    rest.insert(0, m);
+    rest
+}
+
 fn search_result_of(score: i32, searched: i32) -> SearchResult {
     SearchResult {
         m: invalid_move(),
+        pv: Vec::new(),
         score,
         searched,
+        depth: 0,
     }
 }
 
-#[derive(Hash, PartialEq, Eq)]
-struct MoveOrderingKey(Piece, u8);
-type MoveOrderingValue = i32;
-const MOVE_ORDER_TABLE_MAX: i32 = 1000;
-static MOVE_ORDER_TABLE: Lazy<Mutex<HashMap<MoveOrderingKey, MoveOrderingValue>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use board_gen::gen;
 
     #[test]
     fn takes_king_immediately() {
@@ -237,7 +818,7 @@ mod tests {
         b.put_move(&Move::new(&Piece::BKing, 20, 14, false));
 
         println!("{}", b);
-        let result = find_best_move(&b);
+        let result = find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default());
         assert_eq!(result.unwrap().m, Move::new(&Piece::WPawn, 9, 14, false));
     }
 
@@ -254,10 +835,33 @@ mod tests {
         ];
 
         println!("{}", b);
-        let reordered_moves = reorder_moves(&b, &moves);
+        let reordered_moves = reorder_moves(&b, &moves, &MoveOrder::new(), 0, None);
         assert_eq!(reordered_moves, vec![moves[2], moves[1], moves[0]]);
     }
 
+    #[test]
+    fn killers_and_history() {
+        let mut order = MoveOrder::new();
+        let moves = [
+            Move::new(&Piece::BPawn, 15, 10, false),
+            Move::new(&Piece::BGold, 21, 16, false),
+            Move::new(&Piece::BKing, 20, 16, false),
+        ];
+        for m in moves {
+            order.add_killer(3, m);
+        }
+        order.add_killer(3, moves[2]);
+        assert_eq!(order.killers[3], [moves[2], moves[1]]);
+        assert!(!order.is_killer(2, &moves[2]));
+
+        order.add_history(&moves[0], 30, 1000);
+        order.add_history(&moves[1], 10, 1000);
+        assert_eq!(order.history[Piece::BPawn as usize][10], 900);
+        order.add_history(&moves[0], 11, 1000);
+        assert_eq!(order.history[Piece::BPawn as usize][10], 510);
+        assert_eq!(order.history[Piece::BGold as usize][16], 50);
+    }
+
     #[test]
     fn takes_king_immediately_even_our_when_king_will_taken_next_turn() {
         let mut b = Board::init();
@@ -265,7 +869,7 @@ mod tests {
         b.flip_turn();
 
         println!("{}", b);
-        let result = find_best_move(&b);
+        let result = find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default());
         assert_eq!(result.unwrap().m, Move::new(&Piece::BKing, 9, 4, false));
     }
 
@@ -273,7 +877,7 @@ mod tests {
     fn avoid_checkmate() {
         let mut b = gen("41wk,43bg,22bg,15bk");
         b.flip_turn();
-        let result = find_best_move(&b);
+        let result = find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default());
         assert_eq!(result.unwrap().m, Move::new(&Piece::WKing, 1, 0, false));
     }
 
@@ -281,28 +885,324 @@ mod tests {
     fn avoid_checkmate_by_taking() {
         let mut b = gen("51wk,11br,12br,52bs,42bs,15bk");
         b.flip_turn();
-        let result = find_best_move(&b);
+        let result = find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default());
         assert_eq!(result.unwrap().m, Move::new(&Piece::WKing, 0, 5, false));
     }
 
+    #[test]
+    fn no_move_when_checkmated() {
+        let mut b = gen("31wk,32bg,33bp,55bk");
+        b.flip_turn();
+        assert!(
+            find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default()).is_none()
+        );
+    }
+
     #[test]
     fn checkmate_with_1_moves() {
         let b = gen("41wk,43bg,15bk,__bs");
-        let result = find_best_move(&b);
-        assert_eq!(result.unwrap().m, Move::new(&Piece::BSilver, 100, 6, false),);
+        let result = find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default());
+        assert_eq!(result.unwrap().m, Move::drop(&Piece::BSilver, 6),);
     }
 
     #[test]
     fn checkmate_with_3_moves() {
         // https://www.aonoshogi.com/3tetsume/000/002.php
         let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
-        let result = find_best_move(&b);
-        assert_eq!(result.unwrap().m, Move::new(&Piece::BPawnP, 1, 2, false));
+        let result =
+            find_best_move(&b, &TranspositionTable::new(1), &SearchConfig::default()).unwrap();
+        assert_eq!(result.m, Move::new(&Piece::BPawnP, 1, 2, false));
+        // white is mated after any reply.
+        assert_eq!(result.pv.len(), 3);
+        assert_eq!(result.pv[0], result.m);
+        let mut b = b;
+        for m in &result.pv {
+            b.put_move(m);
+        }
+        assert!(b.won == Some(Turn::Black) || move_gen::legal_moves(&b).is_empty());
     }
 
-    fn gen(s: &str) -> Board {
-        let b = board_gen::from_str(s);
-        println!("{}", b);
-        b
+    #[test]
+    fn mate_distance_from_root() {
+        let mut b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let tt = TranspositionTable::new(1);
+        let result = find_best_move(&b, &tt, &SearchConfig::default()).unwrap();
+        assert_eq!(mate_distance(result.score), Some(3));
+
+        // white is mated after 2 plies, whatever it plays.
+        b.put_move(&result.m);
+        let result = find_best_move(&b, &tt, &SearchConfig::default()).unwrap();
+        assert_eq!(mate_distance(result.score), Some(-2));
+
+        assert_eq!(mate_distance(mate_score(5)), Some(5));
+        assert_eq!(mate_distance(mated_score(4)), Some(-4));
+        assert_eq!(mate_distance(1234), None);
+    }
+
+    #[test]
+    fn checks_are_extended() {
+        // the same tsume is solved by searching 1 ply and extending checks.
+        let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let config = SearchConfig {
+            depth: 1,
+            tsume_nodes: 0,
+            ..SearchConfig::default()
+        };
+        let result = find_best_move(&b, &TranspositionTable::new(1), &config).unwrap();
+        assert_eq!(result.m, Move::new(&Piece::BPawnP, 1, 2, false));
+        assert!(result.score > WIN_THRESH);
+    }
+
+    #[test]
+    fn evasions_are_searched_in_quiescence() {
+        // white is mated by the gold, though the king can take it.
+        let mut b = gen("51wk,52bg,54br,55bk");
+        b.flip_turn();
+        let tt = TranspositionTable::new(1);
+        let stop = AtomicBool::new(false);
+        let config = SearchConfig::default();
+        let limits = Limits::default();
+        let mut s = Search::new(&b, &tt, &config, &limits, [&stop, &stop], Instant::now(), 0);
+        let result = rec_search(&mut b, &mut s, 0, 0, -SCORE_LIMIT, SCORE_LIMIT, true);
+        assert_eq!(result.score, -SCORE_LIMIT);
+    }
+
+    #[test]
+    fn transposition_table_saves_search() {
+        let b = Board::init();
+        let tt = TranspositionTable::new(1);
+        let first = find_best_move(&b, &tt, &SearchConfig::default()).unwrap();
+        let second = find_best_move(&b, &tt, &SearchConfig::default()).unwrap();
+        assert!(second.searched < first.searched);
+    }
+
+    #[test]
+    fn repetition_result_is_not_kept_in_tt() {
+        // too shallow for the kings to repeat the root in the tree.
+        let config = SearchConfig {
+            depth: 3,
+            random: false,
+            ..SearchConfig::default()
+        };
+        let mut b = Board::from_sfen("4k/5/5/5/K4 b - 1").unwrap();
+        for (piece, src, dst) in [(Piece::BKing, 20, 21), (Piece::WKing, 4, 3)] {
+            b.put_move(&Move::new(&piece, src, dst, false));
+        }
+        for (piece, src, dst) in [(Piece::BKing, 21, 20), (Piece::WKing, 3, 4)] {
+            b.put_move(&Move::new(&piece, src, dst, false));
+        }
+        let tt = TranspositionTable::new(1);
+        find_best_move(&b, &tt, &config).unwrap();
+        // the root reaches the repetition by playing 4e again.
+        assert_eq!(tt.probe(b.hash).unwrap().depth, 0);
+
+        // the same position without the history is searched to the depth.
+        let b = Board::from_sfen("4k/5/5/5/K4 b - 1").unwrap();
+        find_best_move(&b, &tt, &config).unwrap();
+        assert_eq!(tt.probe(b.hash).unwrap().depth, 3);
+    }
+
+    #[test]
+    fn aborted_iteration_is_discarded() {
+        let b = Board::init();
+        let tt = TranspositionTable::new(1);
+        let config = SearchConfig::default();
+        let stop = AtomicBool::new(true);
+        let result = search(&b, &tt, &config, &Limits::default(), &stop, &|_| {});
+        assert_eq!(result.unwrap().depth, 1);
+
+        let config = SearchConfig {
+            depth: SearchConfig::MAX_DEPTH,
+            ..SearchConfig::default()
+        };
+        let limits = Limits {
+            nodes: Some(2_000),
+            ..Limits::default()
+        };
+        let stop = AtomicBool::new(false);
+        tt.clear();
+        let result = search(&b, &tt, &config, &limits, &stop, &|_| {}).unwrap();
+        assert!(result.depth > 1 && result.depth < SearchConfig::MAX_DEPTH);
+
+        let limits = Limits {
+            movetime: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        let start = Instant::now();
+        search(&b, &tt, &config, &limits, &stop, &|_| {}).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn same_seed_reproduces_game() {
+        let play = |seed| {
+            let mut b = Board::init();
+            let tt = TranspositionTable::new(1);
+            let config = SearchConfig {
+                depth: 2,
+                seed: Some(seed),
+                ..SearchConfig::default()
+            };
+            let mut moves = vec![];
+            for _ in 0..4 {
+                let m = find_best_move(&b, &tt, &config).unwrap().m;
+                b.put_move(&m);
+                moves.push(m);
+            }
+            moves
+        };
+        assert_eq!(play(1), play(1));
+    }
+
+    #[test]
+    fn parallel_search() {
+        let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let config = SearchConfig {
+            depth: 3,
+            threads: 4,
+            ..SearchConfig::default()
+        };
+        let result = find_best_move(&b, &TranspositionTable::new(1), &config).unwrap();
+        assert_eq!(result.m, Move::new(&Piece::BPawnP, 1, 2, false));
+
+        let b = Board::init();
+        let result = find_best_move(&b, &TranspositionTable::new(1), &config).unwrap();
+        assert_eq!(result.depth, config.depth);
+        assert!(move_gen::legal_moves(&b).contains(&result.m));
+    }
+
+    #[test]
+    fn node_limit_is_divided_among_threads() {
+        let b = Board::init();
+        let tt = TranspositionTable::new(1);
+        let config = SearchConfig {
+            threads: 4,
+            ..SearchConfig::default()
+        };
+        let limits = Limits {
+            nodes: Some(1_001),
+            ..Limits::default()
+        };
+        let stop = AtomicBool::new(false);
+        let s = Search::new(&b, &tt, &config, &limits, [&stop, &stop], Instant::now(), 1);
+        assert_eq!(s.max_nodes, Some(251));
+    }
+
+    // nodes searched to `config.depth` in a thread.
+    fn count_nodes(b: &Board, config: &SearchConfig) -> u64 {
+        let tt = TranspositionTable::new(1);
+        let stop = AtomicBool::new(false);
+        let limits = Limits::default();
+        let mut s = Search::new(b, &tt, config, &limits, [&stop, &stop], Instant::now(), 0);
+        iterate(b, &mut s, 1, &|_| {});
+        s.nodes
+    }
+
+    #[test]
+    fn pvs_and_aspiration_window_reduce_nodes() {
+        // the initial board, and the ones in the tests above.
+        let positions = [
+            "11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk",
+            "11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk,w",
+            "41wk,43bg,22bg,15bk,w",
+            "51wk,11br,12br,52bs,42bs,15bk,w",
+            "11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws",
+        ];
+        let config = SearchConfig {
+            depth: 3,
+            random: false,
+            ..SearchConfig::default()
+        };
+        let plain = SearchConfig {
+            pvs: false,
+            aspiration_window: 0,
+            ..config.clone()
+        };
+        let mut nodes = vec![];
+        for p in positions {
+            let b = board_gen::from_str(p).unwrap();
+            let counts = (count_nodes(&b, &plain), count_nodes(&b, &config));
+            println!("{}: {} -> {}", p, counts.0, counts.1);
+            nodes.push(counts);
+        }
+        // small mating positions may get worse by re-searches, but others get better.
+        assert!(nodes[0].1 + nodes[1].1 < nodes[0].0 + nodes[1].0);
+    }
+
+    #[test]
+    fn null_move_and_lmr_reduce_nodes() {
+        let positions = [
+            "11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk",
+            "11wk,21wg,31ws,41wb,51wr,12wp,54bp,15br,25bb,35bs,45bg,55bk,w",
+        ];
+        let config = SearchConfig {
+            depth: 5,
+            random: false,
+            ..SearchConfig::default()
+        };
+        let plain = SearchConfig {
+            null_move: false,
+            lmr: false,
+            ..config.clone()
+        };
+        for p in positions {
+            let b = board_gen::from_str(p).unwrap();
+            let counts = (count_nodes(&b, &plain), count_nodes(&b, &config));
+            println!("{}: {} -> {}", p, counts.0, counts.1);
+            assert!(counts.1 < counts.0);
+        }
+    }
+
+    #[test]
+    fn zugzwang_prone_position() {
+        assert!(is_zugzwang_prone(&gen("11wk,21wg,12wp,54bp,55bk")));
+        assert!(!is_zugzwang_prone(&gen("11wk,21wg,12wp,54bp,55bk,__bg")));
+        assert!(!is_zugzwang_prone(&gen("11wk,21wg,12wp,54bp,55bk,45bg")));
+    }
+
+    #[test]
+    fn aspiration_window_is_widened_to_mate_score() {
+        // the first iteration scores it well, but white is mated.
+        let b = Board::from_sfen("1rsk1/3R1/2S1p/P1K2/1G3 w BGb 22").unwrap();
+        let config = SearchConfig {
+            depth: 2,
+            ..SearchConfig::default()
+        };
+        let limits = Limits {
+            nodes: Some(100_000),
+            ..Limits::default()
+        };
+        let tt = TranspositionTable::new(1);
+        let result = search(&b, &tt, &config, &limits, &AtomicBool::new(false), &|_| {}).unwrap();
+        assert_eq!(result.depth, 2);
+    }
+
+    #[test]
+    fn time_is_allocated_from_clock() {
+        let ms = Duration::from_millis;
+        let limits = |time, increment, byoyomi| Limits {
+            time,
+            increment: ms(increment),
+            byoyomi: ms(byoyomi),
+            ..Limits::default()
+        };
+        assert_eq!(limits(None, 0, 0).allocate_time(), None);
+        assert_eq!(
+            limits(Some(ms(60_000)), 0, 0).allocate_time(),
+            Some(ms(2950))
+        );
+        assert_eq!(
+            limits(Some(ms(60_000)), 1000, 0).allocate_time(),
+            Some(ms(3950))
+        );
+        assert_eq!(limits(Some(ms(0)), 0, 1000).allocate_time(), Some(ms(950)));
+        assert_eq!(limits(None, 0, 1000).allocate_time(), Some(ms(950)));
+        assert_eq!(limits(Some(ms(20)), 0, 0).allocate_time(), Some(ms(0)));
+
+        let movetime = Limits {
+            movetime: Some(ms(500)),
+            ..limits(Some(ms(60_000)), 0, 0)
+        };
+        assert_eq!(movetime.allocate_time(), Some(ms(500)));
     }
 }
@@ -0,0 +1,115 @@
+use super::*;
+
+/// Material which the side to move gains by `m` and the following captures on
+/// the destination, where each side captures with its cheapest piece or stops.
+///
+/// Sliders behind a capturing piece join the exchange, and the pieces which can
+/// promote on the destination do.
+pub fn see(b: &Board, m: &Move) -> i32 {
+    let dst = m.dst as usize;
+    let victim = b.at(dst);
+    if victim == Piece::BKing.of_turn(b.turn.next()) {
+        return value(victim);
+    }
+
+    let mut occupied = b.occupied() | bitboard::bit(dst);
+    if !m.is_drop() {
+        occupied &= !bitboard::bit(m.src as usize);
+    }
+    let mut on_dst = if m.promote {
+        Piece::from_u8(m.piece.as_u8() + 8)
+    } else {
+        m.piece
+    };
+    // gains[i] is the material of the side which made the i-th capture,
+    // when the exchange stops there.
+    let mut gains = vec![value(victim) + value(on_dst) - value(m.piece)];
+    let mut turn = b.turn.next();
+    while let Some((attacker, src)) = cheapest_attacker(b, dst, turn, occupied) {
+        occupied &= !bitboard::bit(src);
+        let piece = promoted(attacker, dst);
+        let gain = value(on_dst) + value(piece) - value(attacker) - gains.last().unwrap();
+        gains.push(gain);
+        on_dst = piece;
+        turn = turn.next();
+    }
+
+    // each side captures only when it is better than stopping.
+    while gains.len() > 1 {
+        let gain = gains.pop().unwrap();
+        let last = gains.last_mut().unwrap();
+        *last = -(-*last).max(gain);
+    }
+    gains[0]
+}
+
+fn value(piece: Piece) -> i32 {
+    evaluator::PIECE_VALUE[piece as usize].abs()
+}
+
+fn cheapest_attacker(b: &Board, pos: usize, turn: Turn, occupied: u32) -> Option<(Piece, usize)> {
+    bitboard::squares(bitboard::attackers_through(b, pos, turn, occupied))
+        .map(|src| (b.at(src), src))
+        .min_by_key(|(piece, _)| value(*piece))
+}
+
+// the piece after moving to `dst`, which promotes if it can.
+fn promoted(piece: Piece, dst: usize) -> Piece {
+    let promotable = (6..=13).contains(&piece.as_u8());
+    if promotable && bitboard::last_rank(piece.turn()) & bitboard::bit(dst) != 0 {
+        Piece::from_u8(piece.as_u8() + 8)
+    } else {
+        piece
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board_gen::gen;
+
+    #[test]
+    fn losing_capture() {
+        // rook takes pawn, and gold takes rook.
+        let b = gen("51wk,55bk,33wp,32wg,35br");
+        let m = Move::new(&Piece::BRook, 22, 12, false);
+        assert_eq!(see(&b, &m), 93 - 1087);
+
+        // nothing defends it.
+        let b = gen("51wk,55bk,33wp,35br");
+        assert_eq!(see(&b, &m), 93);
+    }
+
+    #[test]
+    fn rook_behind_joins_exchange() {
+        // gold takes silver, gold takes gold, and the rook behind takes gold.
+        let b = gen("51wk,55bk,33ws,32wg,34bg,35br");
+        let m = Move::new(&Piece::BGold, 17, 12, false);
+        assert_eq!(see(&b, &m), 528 + 567 - 567);
+
+        // the rook behind the defender also joins.
+        let b = gen("51wk,55bk,33ws,32wg,31wr,34bg,35br");
+        assert_eq!(see(&b, &m), 528 - 567);
+    }
+
+    #[test]
+    fn promotion_is_counted() {
+        let b = gen("51wk,55bk,21wg,22bs");
+        let promote = Move::new(&Piece::BSilver, 8, 3, true);
+        let not_promote = Move::new(&Piece::BSilver, 8, 3, false);
+        assert_eq!(see(&b, &promote), 567 + 582 - 528);
+        assert_eq!(see(&b, &not_promote), 567);
+
+        // pawn takes bishop with promotion.
+        let b = gen("51wk,55bk,43bb,25ws,24wp");
+        let m = Move::new(&Piece::BBishop, 11, 23, false);
+        assert_eq!(see(&b, &m), 528 - 951 - (598 - 93));
+    }
+
+    #[test]
+    fn taking_king_is_best() {
+        let b = gen("51wk,55bk,52br,53wg");
+        let m = Move::new(&Piece::BRook, 5, 0, true);
+        assert_eq!(see(&b, &m), 5000);
+    }
+}
@@ -0,0 +1,357 @@
+use super::*;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TsumeResult {
+    // moves to mate, where the defender delays the mate as long as it can.
+    Mate(Vec<Move>),
+    NoMate,
+    // the node budget has run out before proving either.
+    Unknown,
+}
+
+pub const DEFAULT_NODES: u64 = 1_000_000;
+
+const INFINITE: u32 = u32::MAX;
+// longer lines are given up, and then no mate is reported as unknown.
+const MAX_PLY: usize = 63;
+
+/// Proves whether the side to move mates by checking moves only, with df-pn.
+///
+/// It visits `max_nodes` positions at most, and returns the result with the
+/// number of visited positions.
+pub fn solve(b: &Board, max_nodes: u64) -> (TsumeResult, u64) {
+    solve_within(b, max_nodes, MAX_PLY)
+}
+
+fn solve_within(b: &Board, max_nodes: u64, max_ply: usize) -> (TsumeResult, u64) {
+    // the king left to be taken can't be saved.
+    let king = Piece::BKing.of_turn(b.turn.next());
+    if let Some(m) = move_gen::taking_moves(b)
+        .into_iter()
+        .find(|m| b.at(m.dst as usize) == king)
+    {
+        return (TsumeResult::Mate(vec![m]), 1);
+    }
+
+    let mut solver = Solver {
+        table: HashMap::new(),
+        nodes: 0,
+        max_nodes,
+        max_ply,
+        max_ply_reached: false,
+    };
+    let mut b = b.clone();
+    let result = match solver.mid(&mut b, INFINITE, INFINITE, true, 0) {
+        (0, _) => TsumeResult::Mate(solver.mating_moves(&mut b)),
+        // a line cut at `max_ply` may be a mate.
+        (_, 0) if !solver.max_ply_reached => TsumeResult::NoMate,
+        _ => TsumeResult::Unknown,
+    };
+    (result, solver.nodes)
+}
+
+struct Solver {
+    // proof and disproof numbers of the attacker's mate, per position.
+    table: HashMap<u64, (u32, u32)>,
+    nodes: u64,
+    max_nodes: u64,
+    max_ply: usize,
+    max_ply_reached: bool,
+}
+
+impl Solver {
+    // searches until the proof or disproof number reaches its threshold.
+    fn mid(
+        &mut self,
+        b: &mut Board,
+        th_pn: u32,
+        th_dn: u32,
+        attacker: bool,
+        ply: usize,
+    ) -> (u32, u32) {
+        self.nodes += 1;
+        if let Some(numbers) = self.cut(b, ply) {
+            return numbers;
+        }
+        let moves = if attacker {
+            move_gen::check_moves(b)
+        } else {
+            move_gen::evasion_moves(b)
+        };
+        if moves.is_empty() {
+            let numbers = if attacker {
+                (INFINITE, 0)
+            } else {
+                (0, INFINITE)
+            };
+            self.table.insert(b.hash, numbers);
+            return numbers;
+        }
+
+        loop {
+            let (pn, dn, best, second) = self.children(b, &moves, attacker, ply);
+            if pn >= th_pn || dn >= th_dn || self.nodes >= self.max_nodes {
+                self.table.insert(b.hash, (pn, dn));
+                return (pn, dn);
+            }
+            let (m, (child_pn, child_dn)) = best;
+            // the child is searched until it gets worse than the second best.
+            let (th_pn, th_dn) = if attacker {
+                (
+                    th_pn.min(second.saturating_add(1)),
+                    th_dn.saturating_sub(dn - child_dn),
+                )
+            } else {
+                (
+                    th_pn.saturating_sub(pn - child_pn),
+                    th_dn.min(second.saturating_add(1)),
+                )
+            };
+            let undo = b.put_move(&m);
+            self.mid(b, th_pn, th_dn, !attacker, ply + 1);
+            b.undo_move(&m, undo);
+        }
+    }
+
+    // numbers of the position from the children, with the best child and the
+    // second best number of it.
+    fn children(
+        &mut self,
+        b: &mut Board,
+        moves: &[Move],
+        attacker: bool,
+        ply: usize,
+    ) -> (u32, u32, (Move, (u32, u32)), u32) {
+        // the attacker needs one of the moves to mate, and the defender all of them.
+        let mut best = (moves[0], (INFINITE, INFINITE));
+        let (mut min, mut second, mut sum) = (INFINITE, INFINITE, 0u32);
+        for m in moves {
+            let numbers = self.numbers_after(b, m, !attacker, ply + 1);
+            let (selected, summed) = if attacker {
+                numbers
+            } else {
+                (numbers.1, numbers.0)
+            };
+            sum = sum.saturating_add(summed);
+            if selected < min {
+                second = min;
+                min = selected;
+                best = (*m, numbers);
+            } else if selected < second {
+                second = selected;
+            }
+        }
+        if attacker {
+            (min, sum, best, second)
+        } else {
+            (sum, min, best, second)
+        }
+    }
+
+    fn numbers_after(&mut self, b: &mut Board, m: &Move, attacker: bool, ply: usize) -> (u32, u32) {
+        let undo = b.put_move(m);
+        let numbers = match self
+            .cut(b, ply)
+            .or_else(|| self.table.get(&b.hash).copied())
+        {
+            Some(numbers) => numbers,
+            None => {
+                // a position with fewer moves is easier to decide.
+                let numbers = if attacker {
+                    match move_gen::check_moves(b).len() as u32 {
+                        0 => (INFINITE, 0),
+                        n => (1, n),
+                    }
+                } else {
+                    match move_gen::evasion_moves(b).len() as u32 {
+                        0 => (0, INFINITE),
+                        n => (n, 1),
+                    }
+                };
+                self.table.insert(b.hash, numbers);
+                numbers
+            }
+        };
+        b.undo_move(m, undo);
+        numbers
+    }
+
+    // repeating checks can't mate, and too long lines are given up.
+    // they depend on the path, so they are not stored in the table.
+    fn cut(&mut self, b: &Board, ply: usize) -> Option<(u32, u32)> {
+        if ply >= self.max_ply {
+            self.max_ply_reached = true;
+            return Some((INFINITE, 0));
+        }
+        if b.repetition_count() >= 2 {
+            return Some((INFINITE, 0));
+        }
+        None
+    }
+
+    // follows the proven positions, where the attacker mates the fastest and
+    // the defender delays it.
+    fn mating_moves(&self, b: &mut Board) -> Vec<Move> {
+        let mut lengths = HashMap::new();
+        let mut moves = Vec::new();
+        let mut attacker = true;
+        loop {
+            let candidates = if attacker {
+                move_gen::check_moves(b)
+            } else {
+                move_gen::evasion_moves(b)
+            };
+            let mut best: Option<(Move, u32)> = None;
+            for m in candidates {
+                let undo = b.put_move(&m);
+                let length = self.mate_length(b, !attacker, &mut lengths);
+                b.undo_move(&m, undo);
+                if let Some(length) = length {
+                    let better =
+                        best.is_none_or(|(_, l)| if attacker { length < l } else { length > l });
+                    if better {
+                        best = Some((m, length));
+                    }
+                }
+            }
+            match best {
+                Some((m, _)) => {
+                    b.put_move(&m);
+                    moves.push(m);
+                    attacker = !attacker;
+                }
+                None => return moves,
+            }
+        }
+    }
+
+    // plies to mate in the proven positions.
+    fn mate_length(
+        &self,
+        b: &mut Board,
+        attacker: bool,
+        lengths: &mut HashMap<u64, Option<u32>>,
+    ) -> Option<u32> {
+        if let Some(length) = lengths.get(&b.hash) {
+            return *length;
+        }
+        if self.table.get(&b.hash).map(|e| e.0) != Some(0) {
+            return None;
+        }
+        // a position in the current line is not a mate.
+        lengths.insert(b.hash, None);
+        let mut length = None;
+        if attacker {
+            for m in move_gen::check_moves(b) {
+                let undo = b.put_move(&m);
+                let l = self.mate_length(b, false, lengths);
+                b.undo_move(&m, undo);
+                if let Some(l) = l {
+                    length = Some(length.map_or(l + 1, |length: u32| length.min(l + 1)));
+                }
+            }
+        } else {
+            length = Some(0);
+            for m in move_gen::evasion_moves(b) {
+                let undo = b.put_move(&m);
+                let l = self.mate_length(b, true, lengths);
+                b.undo_move(&m, undo);
+                length = match (length, l) {
+                    (Some(length), Some(l)) => Some(length.max(l + 1)),
+                    _ => None,
+                };
+            }
+        }
+        lengths.insert(b.hash, length);
+        length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board_gen::gen;
+
+    fn mating_moves(b: &Board) -> Vec<Move> {
+        match solve(b, DEFAULT_NODES).0 {
+            TsumeResult::Mate(moves) => moves,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn mate_in_1() {
+        let b = gen("51wk,53bs,45br,15bk,__bg");
+        assert_eq!(mating_moves(&b), vec![Move::drop(&Piece::BGold, 1)]);
+    }
+
+    // every move of the attacker checks, and the defender is mated at last.
+    fn assert_mate(b: &Board, moves: &[Move]) {
+        let mut b = b.clone();
+        for (i, m) in moves.iter().enumerate() {
+            assert!(move_gen::legal_moves(&b).contains(m), "{}", m);
+            b.put_move(m);
+            if i % 2 == 0 {
+                assert!(move_gen::is_checked(&b), "{}", m);
+            }
+        }
+        assert!(move_gen::is_checked(&b));
+        assert!(move_gen::evasion_moves(&b).is_empty());
+    }
+
+    #[test]
+    fn mate_in_3() {
+        // https://www.aonoshogi.com/3tetsume/000/002.php
+        let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let moves = mating_moves(&b);
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0], Move::new(&Piece::BPawnP, 1, 2, false));
+        assert_mate(&b, &moves);
+    }
+
+    #[test]
+    fn long_mate() {
+        // found in self-play, where the search at depth 4 finds it too.
+        let b = Board::from_sfen("1r3/3kp/1g3/P3K/1G3 b B2Srb 27").unwrap();
+        let moves = mating_moves(&b);
+        assert_eq!(moves.len(), 9);
+        assert_mate(&b, &moves);
+    }
+
+    #[test]
+    fn king_left_in_check_is_taken() {
+        let mut b = Board::init();
+        b.put_move(&Move::new(&Piece::BKing, 20, 14, false));
+        assert_eq!(
+            solve(&b, DEFAULT_NODES),
+            (
+                TsumeResult::Mate(vec![Move::new(&Piece::WPawn, 9, 14, false)]),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn no_mate() {
+        // the king escapes to 4a.
+        let b = gen("51wk,53bs,15bk,__bp");
+        assert_eq!(solve(&b, DEFAULT_NODES).0, TsumeResult::NoMate);
+        assert_eq!(solve(&Board::init(), DEFAULT_NODES).0, TsumeResult::NoMate);
+    }
+
+    #[test]
+    fn mate_beyond_max_ply_is_unknown() {
+        let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let (result, _) = solve_within(&b, DEFAULT_NODES, 2);
+        assert_eq!(result, TsumeResult::Unknown);
+    }
+
+    #[test]
+    fn node_budget() {
+        let b = gen("11wr,21wk,41bP,52bR,55bk,__bs,__wg,__ws");
+        let (result, nodes) = solve(&b, 2);
+        assert_eq!(result, TsumeResult::Unknown);
+        assert_eq!(nodes, 2);
+    }
+}
@@ -0,0 +1,221 @@
+use super::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Which side of the true score `Entry::score` is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // the score failed high, and the true one is greater or equal.
+    Lower,
+    // the score failed low, and the true one is less or equal.
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub hash: u64,
+    // remaining depth which the score is searched with.
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: i32,
+    pub m: Move,
+}
+
+impl Entry {
+    // score: 32 bits, depth: 8 bits, bound: 2 bits, move: 18 bits
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let m = (self.m.piece as u64) << 13
+            | (self.m.src as u64) << 6
+            | (self.m.dst as u64) << 1
+            | self.m.promote as u64;
+        (self.score as u32 as u64) << 32 | (self.depth as u64) << 24 | bound << 22 | m
+    }
+
+    fn unpack(hash: u64, data: u64) -> Entry {
+        let bound = match (data >> 22) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Entry {
+            hash,
+            depth: (data >> 24) as u8,
+            bound,
+            score: (data >> 32) as u32 as i32,
+            m: Move {
+                piece: Piece::from_u8((data >> 13) as u8 & 31),
+                src: (data >> 6) as u8 & 127,
+                dst: (data >> 1) as u8 & 31,
+                promote: data & 1 == 1,
+            },
+        }
+    }
+}
+
+/// Fixed size hash table of searched positions, which is kept between searches.
+///
+/// It is shared by search threads without locks. Each slot keeps the hash xor-ed
+/// with the data, so a slot torn by concurrent writes is just not found.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+impl TranspositionTable {
+    pub const DEFAULT_MB: usize = 16;
+    pub const MAX_MB: usize = 4096;
+
+    /// Allocates the table in `mb` megabytes at most.
+    pub fn new(mb: usize) -> TranspositionTable {
+        let len = (mb << 20) / std::mem::size_of::<[AtomicU64; 2]>();
+        // round down to a power of 2 to index by mask.
+        let len = if len == 0 {
+            1
+        } else {
+            1 << (usize::BITS - 1 - len.leading_zeros())
+        };
+        TranspositionTable {
+            slots: (0..len)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        let slot = &self.slots[self.index(hash)];
+        let key = slot[0].load(Ordering::Relaxed);
+        let data = slot[1].load(Ordering::Relaxed);
+        // data of an empty slot is 0, as any entry has its bound.
+        if data == 0 || key ^ data != hash {
+            return None;
+        }
+        Some(Entry::unpack(hash, data))
+    }
+
+    /// Stores `entry`, replacing the one of another position or a shallower one.
+    pub fn store(&self, entry: Entry) {
+        if let Some(old) = self.probe(entry.hash) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let slot = &self.slots[self.index(entry.hash)];
+        let data = entry.pack();
+        slot[0].store(entry.hash ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        hash as usize & (self.slots.len() - 1)
+    }
+}
+
+// Mate scores count plies from the root, but the same position can appear at
+// another ply. They are stored as plies from the position instead.
+
+pub fn score_to_tt(score: i32, ply: u8) -> i32 {
+    if score > searcher::WIN_THRESH {
+        score + ply as i32
+    } else if score < -searcher::WIN_THRESH {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply: u8) -> i32 {
+    if score > searcher::WIN_THRESH {
+        score - ply as i32
+    } else if score < -searcher::WIN_THRESH {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: u64, depth: u8) -> Entry {
+        Entry {
+            hash,
+            depth,
+            bound: Bound::Exact,
+            score: 0,
+            m: Move::new(&Piece::BPawn, 15, 10, false),
+        }
+    }
+
+    #[test]
+    fn deeper_entry_is_kept() {
+        let tt = TranspositionTable::new(1);
+        tt.store(entry(42, 3));
+        tt.store(entry(42, 2));
+        assert_eq!(tt.probe(42), Some(entry(42, 3)));
+        assert_eq!(tt.probe(43), None);
+
+        // another position in the same slot replaces it.
+        let other = 42 + tt.slots.len() as u64;
+        tt.store(entry(other, 1));
+        assert_eq!(tt.probe(42), None);
+        assert_eq!(tt.probe(other), Some(entry(other, 1)));
+
+        tt.clear();
+        assert_eq!(tt.probe(other), None);
+    }
+
+    #[test]
+    fn entry_is_packed_into_64_bits() {
+        let tt = TranspositionTable::new(1);
+        let entries = [
+            Entry {
+                hash: 1,
+                depth: 64,
+                bound: Bound::Lower,
+                score: -searcher::SCORE_LIMIT + 3,
+                m: Move::drop(&Piece::WRook, 24),
+            },
+            Entry {
+                hash: 2,
+                depth: 0,
+                bound: Bound::Upper,
+                score: 12345,
+                m: Move::new(&Piece::BSilver, 7, 2, true),
+            },
+        ];
+        for e in entries {
+            tt.store(e);
+            assert_eq!(tt.probe(e.hash), Some(e));
+        }
+
+        // a slot torn by other threads is not found.
+        let slot = &tt.slots[tt.index(2)];
+        slot[1].fetch_xor(1 << 40, Ordering::Relaxed);
+        assert_eq!(tt.probe(2), None);
+    }
+
+    #[test]
+    fn mate_score_is_relative_to_position() {
+        // mate at ply 5 is found at ply 2, and the position appears again at ply 4.
+        let score = searcher::SCORE_LIMIT - 5;
+        let stored = score_to_tt(score, 2);
+        assert_eq!(score_from_tt(stored, 4), searcher::SCORE_LIMIT - 7);
+        assert_eq!(
+            score_from_tt(score_to_tt(-score, 2), 4),
+            -searcher::SCORE_LIMIT + 7
+        );
+        assert_eq!(score_from_tt(score_to_tt(300, 2), 4), 300);
+    }
+}
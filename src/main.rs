@@ -1,21 +1,75 @@
 mod cui;
 mod game;
 mod logic;
+mod usi;
 
 use game::*;
 use getopts::Options;
 use logic::*;
 use std::collections::HashMap;
 use std::env;
+use std::fmt::Debug;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::time::SystemTime;
 
 fn main() {
     let mut opts = Options::new();
     opts.optflag("b", "black", "play black");
     opts.optflag("w", "white", "play white");
+    opts.optflag("", "usi", "run as an USI engine");
+    opts.optopt("", "perft", "count legal move sequences and exit", "DEPTH");
+    opts.optopt(
+        "",
+        "tsume",
+        "solve the mate of the side to move and exit",
+        "SFEN",
+    );
+    opts.optopt("", "hash", "size of the transposition table", "MB");
+    opts.optopt("", "depth", "depth of the search", "DEPTH");
+    opts.optopt("", "qdepth", "depth of the quiescence search", "DEPTH");
+    opts.optopt("", "seed", "seed of the random move ordering", "SEED");
+    opts.optflag("", "no-random", "order moves without randomness");
+    opts.optflag("", "no-null-move", "search without null-move pruning");
+    opts.optflag("", "no-lmr", "search without late move reductions");
+    opts.optopt("", "threads", "number of search threads", "THREADS");
     let args: Vec<String> = env::args().collect();
+    let mut hash_mb = tt::TranspositionTable::DEFAULT_MB;
+    let mut config = searcher::SearchConfig::default();
     let opt = match opts.parse(&args[1..]) {
         Ok(m) => {
+            if m.opt_present("usi") {
+                usi::run();
+                return;
+            }
+            let max_depth = searcher::SearchConfig::MAX_DEPTH;
+            if let Some(depth) = m.opt_str("perft") {
+                perft(parse_in("perft", &depth, 1..=max_depth));
+                return;
+            }
+            if let Some(sfen) = m.opt_str("tsume") {
+                tsume(&sfen);
+                return;
+            }
+            if let Some(mb) = m.opt_str("hash") {
+                hash_mb = parse_in("hash", &mb, 1..=tt::TranspositionTable::MAX_MB);
+            }
+            if let Some(depth) = m.opt_str("depth") {
+                config.depth = parse_in("depth", &depth, 1..=max_depth);
+            }
+            if let Some(qdepth) = m.opt_str("qdepth") {
+                config.qdepth = parse_in("qdepth", &qdepth, 0..=max_depth);
+            }
+            if let Some(threads) = m.opt_str("threads") {
+                config.threads =
+                    parse_in("threads", &threads, 1..=searcher::SearchConfig::MAX_THREADS);
+            }
+            config.seed = m
+                .opt_str("seed")
+                .map(|seed| parse_in("seed", &seed, 0..=u64::MAX));
+            config.random = !m.opt_present("no-random");
+            config.null_move = !m.opt_present("no-null-move");
+            config.lmr = !m.opt_present("no-lmr");
             let mut opt: HashMap<Turn, bool> = HashMap::new();
             opt.insert(Turn::Black, m.opt_present("b"));
             opt.insert(Turn::White, m.opt_present("w"));
@@ -27,31 +81,52 @@ fn main() {
     };
 
     let mut b = Board::init();
+    let tt = tt::TranspositionTable::new(hash_mb);
     println!("{}", b);
+    println!("SFEN: {}", b.to_sfen());
 
     let mut evaluated = 0;
     let start = SystemTime::now();
     for i in 0..1000 {
-        if b.won.is_some() {
-            println!("Game has finished in {} moves", i);
+        if b.won.is_none() && move_gen::legal_moves(&b).is_empty() {
+            if move_gen::is_checked(&b) {
+                println!("Checkmate.");
+            } else {
+                println!("No legal moves.");
+            }
+            b.won = Some(b.turn.next());
+        }
+        if let Some(winner) = b.won {
+            if b.repetition_count() >= 4 {
+                println!("Sennichite.");
+            }
+            println!("Game has finished in {} moves, {:?} won", i, winner);
             break;
         }
 
         let selected_move = if *opt.get(&b.turn).unwrap() {
             cui::user_input(&b)
         } else {
-            let best_move = searcher::find_best_move(&b).unwrap();
+            let best_move = searcher::find_best_move(&b, &tt, &config).unwrap();
+            let evaluation = match searcher::mate_distance(best_move.score) {
+                Some(plies) if plies >= 0 => format!("mate in {}", plies),
+                Some(plies) => format!("mated in {}", -plies),
+                None => best_move.score.to_string(),
+            };
             println!(
                 "Evaluated {} boards, Evaluation {}",
-                best_move.searched, best_move.score
+                best_move.searched, evaluation
             );
+            let pv: Vec<String> = best_move.pv.iter().map(|m| m.to_string()).collect();
+            println!("PV: {}", pv.join(" "));
             evaluated += best_move.searched;
             best_move.m
         };
 
-        println!("Selected move : {:?}", selected_move);
+        println!("Selected move : {}", selected_move);
         b.put_move(&selected_move);
         println!("{}", b);
+        println!("SFEN: {}", b.to_sfen());
         println!("==========================");
 
         if i == 299 {
@@ -68,3 +143,51 @@ fn main() {
         (evaluated as u128) * 1000 / ms
     );
 }
+
+// parses the value of `--name`, which must be in `range`.
+fn parse_in<T: FromStr + PartialOrd + Debug>(
+    name: &str,
+    value: &str,
+    range: RangeInclusive<T>,
+) -> T {
+    match value.parse() {
+        Ok(v) if range.contains(&v) => v,
+        _ => panic!("--{} must be in {:?}, but got {}", name, range, value),
+    }
+}
+
+fn perft(max_depth: u8) {
+    let mut b = Board::init();
+    for depth in 1..=max_depth {
+        let start = SystemTime::now();
+        let nodes = move_gen::perft(&mut b, depth);
+        let ms = start.elapsed().unwrap().as_millis().max(1);
+        println!(
+            "perft {}: {} nodes in {} ms. ({} nodes/sec)",
+            depth,
+            nodes,
+            ms,
+            (nodes as u128) * 1000 / ms
+        );
+    }
+}
+
+fn tsume(sfen: &str) {
+    let b = match Board::from_sfen(sfen) {
+        Ok(b) => b,
+        Err(e) => panic!("--tsume must be valid SFEN, but got {}: {}", sfen, e),
+    };
+    println!("{}", b);
+    let start = SystemTime::now();
+    let (result, nodes) = tsume::solve(&b, tsume::DEFAULT_NODES);
+    let ms = start.elapsed().unwrap().as_millis();
+    match result {
+        tsume::TsumeResult::Mate(moves) => {
+            let moves: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+            println!("Mate in {}: {}", moves.len(), moves.join(" "));
+        }
+        tsume::TsumeResult::NoMate => println!("No mate."),
+        tsume::TsumeResult::Unknown => println!("Unknown."),
+    }
+    println!("Visited {} positions in {} ms.", nodes, ms);
+}
@@ -0,0 +1,532 @@
+use super::*;
+use logic::searcher::{Limits, SearchConfig, SearchResult};
+use logic::tt::TranspositionTable;
+use std::io;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Runs as an USI engine over stdin/stdout until `quit` is received.
+pub fn run() {
+    let mut usi = Usi::new(io::stdout());
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap();
+        if !usi.handle(&line) {
+            break;
+        }
+    }
+    usi.stop_search();
+}
+
+struct Usi<W: Write + Send + 'static> {
+    board: Board,
+    config: SearchConfig,
+    tt: Arc<TranspositionTable>,
+    // the search runs in another thread to accept `stop` meanwhile.
+    search: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    out: Arc<Mutex<W>>,
+}
+
+impl<W: Write + Send + 'static> Usi<W> {
+    fn new(out: W) -> Usi<W> {
+        Usi {
+            board: Board::init(),
+            config: SearchConfig::default(),
+            tt: Arc::new(TranspositionTable::new(TranspositionTable::DEFAULT_MB)),
+            search: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            out: Arc::new(Mutex::new(out)),
+        }
+    }
+
+    // returns false when the engine should quit.
+    fn handle(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        let cmd = tokens.next();
+        let args: Vec<&str> = tokens.collect();
+        // commands other than these may change what the search is using.
+        if !matches!(cmd, Some("usi") | Some("isready") | None) {
+            self.stop_search();
+        }
+        match cmd {
+            Some("usi") => {
+                self.println("id name gogo_shogi");
+                self.println("id author toduq");
+                self.println(&format!(
+                    "option name USI_Hash type spin default {} min 1 max {}",
+                    TranspositionTable::DEFAULT_MB,
+                    TranspositionTable::MAX_MB
+                ));
+                self.println(&format!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    SearchConfig::MAX_THREADS
+                ));
+                self.println("option name Seed type string default random");
+                self.println("usiok");
+            }
+            Some("isready") => self.println("readyok"),
+            Some("usinewgame") => {
+                self.board = Board::init();
+                self.tt.clear();
+            }
+            Some("position") => {
+                if let Err(e) = self.position(&args) {
+                    self.println(&format!("info string {}", e));
+                }
+            }
+            Some("setoption") => {
+                if let Err(e) = self.setoption(&args) {
+                    self.println(&format!("info string {}", e));
+                }
+            }
+            Some("go") => match self.go_params(&args) {
+                Ok((config, limits)) => self.go(config, limits),
+                Err(e) => self.println(&format!("info string {}", e)),
+            },
+            Some("quit") => return false,
+            Some("stop") | Some("gameover") | Some("ponderhit") => {}
+            Some(cmd) => self.println(&format!("info string unknown command {}", cmd)),
+            None => {}
+        }
+        true
+    }
+
+    fn println(&self, line: &str) {
+        let mut out = self.out.lock().unwrap();
+        writeln!(out, "{}", line).unwrap();
+        out.flush().unwrap();
+    }
+
+    fn position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|s| *s == "moves");
+        let (setup, moves) = match moves_at {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &args[args.len()..]),
+        };
+        let mut board = match setup.first() {
+            Some(&"startpos") => Board::init(),
+            Some(&"sfen") => Board::from_sfen(&setup[1..].join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err(format!("unsupported position {}", setup.join(" "))),
+        };
+        for s in moves {
+            match parse_move(&board, s) {
+                Some(m) => {
+                    board.put_move(&m);
+                }
+                None => return Err(format!("invalid move {}", s)),
+            }
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    // setoption name <id> value <x>
+    fn setoption(&mut self, args: &[&str]) -> Result<(), String> {
+        match args {
+            ["name", "USI_Hash", "value", mb] => {
+                let mb = match mb.parse() {
+                    Ok(n) if (1..=TranspositionTable::MAX_MB).contains(&n) => n,
+                    _ => return Err(format!("invalid USI_Hash {}", mb)),
+                };
+                self.tt = Arc::new(TranspositionTable::new(mb));
+                Ok(())
+            }
+            ["name", "Threads", "value", threads] => {
+                self.config.threads = match threads.parse() {
+                    Ok(n) if (1..=SearchConfig::MAX_THREADS).contains(&n) => n,
+                    _ => return Err(format!("invalid Threads {}", threads)),
+                };
+                Ok(())
+            }
+            ["name", "Seed", "value", "random"] => {
+                self.config.seed = None;
+                Ok(())
+            }
+            ["name", "Seed", "value", seed] => {
+                let seed = seed.parse().map_err(|_| format!("invalid Seed {}", seed))?;
+                self.config.seed = Some(seed);
+                Ok(())
+            }
+            ["name", name, ..] => Err(format!("unknown option {}", name)),
+            _ => Err(format!("invalid setoption {}", args.join(" "))),
+        }
+    }
+
+    // go [btime <x>] [wtime <x>] [binc <x>] [winc <x>] [byoyomi <x>]
+    //    [movetime <x>] [depth <x>] [nodes <x>] [infinite] [ponder]
+    fn go_params(&self, args: &[&str]) -> Result<(SearchConfig, Limits), String> {
+        let mut limits = Limits::default();
+        let mut depth = None;
+        let mut timed = false;
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == "infinite" || args[i] == "ponder" {
+                timed |= args[i] == "infinite";
+                // bestmove waits for `ponderhit` or `stop` while pondering.
+                limits.infinite = true;
+                i += 1;
+                continue;
+            }
+            let value: u64 = match args.get(i + 1).map(|v| v.parse()) {
+                Some(Ok(v)) => v,
+                _ => return Err(format!("invalid go {}", args[i..].join(" "))),
+            };
+            let ms = Duration::from_millis(value);
+            let own = |turn| turn == self.board.turn;
+            match args[i] {
+                "btime" | "wtime" if own(turn_of(args[i])) => limits.time = Some(ms),
+                "binc" | "winc" if own(turn_of(args[i])) => limits.increment = ms,
+                "byoyomi" => limits.byoyomi = ms,
+                "movetime" => limits.movetime = Some(ms),
+                "depth" if value == 0 => return Err("invalid go depth 0".to_string()),
+                "depth" => depth = Some(value.min(SearchConfig::MAX_DEPTH as u64) as u8),
+                "nodes" => limits.nodes = Some(value),
+                "btime" | "wtime" | "binc" | "winc" => {}
+                _ => return Err(format!("unknown go parameter {}", args[i])),
+            }
+            timed |= !matches!(args[i], "depth");
+            i += 2;
+        }
+        // without a depth, search as deep as the time allows.
+        let config = SearchConfig {
+            depth: match depth {
+                Some(depth) => depth,
+                None if timed => SearchConfig::MAX_DEPTH,
+                None => self.config.depth,
+            },
+            ..self.config.clone()
+        };
+        Ok((config, limits))
+    }
+
+    fn go(&mut self, config: SearchConfig, limits: Limits) {
+        self.stop.store(false, Ordering::Relaxed);
+        let board = self.board.clone();
+        let tt = Arc::clone(&self.tt);
+        let stop = Arc::clone(&self.stop);
+        let out = Arc::clone(&self.out);
+        self.search = Some(thread::spawn(move || {
+            let report = |result: &SearchResult| {
+                let mut out = out.lock().unwrap();
+                writeln!(out, "{}", info_of(result)).unwrap();
+                out.flush().unwrap();
+            };
+            let result = searcher::search(&board, &tt, &config, &limits, &stop, &report);
+            let mut out = out.lock().unwrap();
+            match result {
+                Some(result) => writeln!(out, "bestmove {}", move_to_usi(&result.m)).unwrap(),
+                None => writeln!(out, "bestmove resign").unwrap(),
+            }
+            out.flush().unwrap();
+        }));
+    }
+
+    // stops the search if running, and waits for `bestmove`.
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            search.join().unwrap();
+        }
+    }
+}
+
+fn turn_of(param: &str) -> Turn {
+    if param.starts_with('b') {
+        Turn::Black
+    } else {
+        Turn::White
+    }
+}
+
+/// Formats a move like `2e3d`, `3b3a+` or `S*3c`.
+pub fn move_to_usi(m: &Move) -> String {
+    if m.is_drop() {
+        let letter = match m.piece.of_turn(Turn::Black) {
+            Piece::BGold => 'G',
+            Piece::BSilver => 'S',
+            Piece::BBishop => 'B',
+            Piece::BRook => 'R',
+            _ => 'P',
+        };
+        format!("{}*{}", letter, square_to_usi(m.dst))
+    } else {
+        format!(
+            "{}{}{}",
+            square_to_usi(m.src),
+            square_to_usi(m.dst),
+            if m.promote { "+" } else { "" }
+        )
+    }
+}
+
+/// Formats the result of an iteration like `info depth 3 score cp 120 nodes 500 pv 2e3d 4a3b`.
+fn info_of(result: &SearchResult) -> String {
+    let pv: Vec<String> = result.pv.iter().map(move_to_usi).collect();
+    format!(
+        "info depth {} score {} nodes {} pv {}",
+        result.depth,
+        score_to_usi(result.score),
+        result.searched,
+        pv.join(" ")
+    )
+}
+
+/// Formats a score like `cp 120` or `mate -3`, which is mated in 3 plies.
+fn score_to_usi(score: i32) -> String {
+    match searcher::mate_distance(score) {
+        Some(plies) => format!("mate {}", plies),
+        None => format!("cp {}", score),
+    }
+}
+
+/// Finds the legal move which is expressed as `s` in USI notation.
+pub fn parse_move(b: &Board, s: &str) -> Option<Move> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 4 {
+        return None;
+    }
+    if bytes[1] == b'*' {
+        let piece = board_gen::piece_repr(bytes[0].to_ascii_lowercase()).of_turn(b.turn);
+        let dst = usi_to_square(bytes[2], bytes[3])?;
+        move_gen::legal_moves(b)
+            .into_iter()
+            .find(|m| m.is_drop() && m.piece == piece && m.dst == dst)
+    } else {
+        let src = usi_to_square(bytes[0], bytes[1])?;
+        let dst = usi_to_square(bytes[2], bytes[3])?;
+        let promote = bytes.len() >= 5 && bytes[4] == b'+';
+        move_gen::legal_moves(b)
+            .into_iter()
+            .find(|m| m.src == src && m.dst == dst && m.promote == promote)
+    }
+}
+
+fn square_to_usi(pos: u8) -> String {
+    format!("{}{}", 5 - pos % 5, (b'a' + pos / 5) as char)
+}
+
+fn usi_to_square(file: u8, rank: u8) -> Option<u8> {
+    match (file, rank) {
+        (b'1'..=b'5', b'a'..=b'e') => Some((rank - b'a') * 5 + (b'5' - file)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_notation() {
+        let b = Board::init();
+        let m = parse_move(&b, "2e3d").unwrap();
+        assert_eq!(m, Move::new(&Piece::BBishop, 23, 17, false));
+        assert_eq!(move_to_usi(&m), "2e3d");
+
+        let b = board_gen::from_str("41wk,43bg,15bk,__bs").unwrap();
+        let m = parse_move(&b, "S*4b").unwrap();
+        assert_eq!(m, Move::drop(&Piece::BSilver, 6));
+        assert_eq!(move_to_usi(&m), "S*4b");
+
+        assert_eq!(parse_move(&b, "S*6b"), None);
+        assert_eq!(parse_move(&b, "G*4b"), None);
+    }
+
+    fn output(usi: &Usi<Vec<u8>>) -> String {
+        String::from_utf8(usi.out.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn position_with_moves() {
+        let mut usi = Usi::new(Vec::new());
+        assert!(usi.handle("position startpos moves 2e3d 4a3b"));
+        let mut expected = Board::init();
+        expected.put_move(&Move::new(&Piece::BBishop, 23, 17, false));
+        expected.put_move(&Move::new(&Piece::WBishop, 1, 7, false));
+        assert_eq!(usi.board, expected);
+        assert!(output(&usi).is_empty());
+    }
+
+    #[test]
+    fn position_with_sfen() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position sfen 2k2/5/5/5/4K b S 1 moves S*3b");
+        assert_eq!(usi.board.to_sfen(), "2k2/2S2/5/5/4K w - 2");
+
+        usi.handle("position sfen 2k2/5/5/5/4K");
+        assert_eq!(usi.board.to_sfen(), "2k2/2S2/5/5/4K w - 2");
+        assert!(output(&usi).starts_with("info string"));
+    }
+
+    #[test]
+    fn handshake_and_go() {
+        let mut usi = Usi::new(Vec::new());
+        let lines = [
+            "usi",
+            "setoption name USI_Hash value 1",
+            "setoption name Seed value 42",
+            "setoption name Threads value 2",
+            "isready",
+            "usinewgame",
+            "position startpos",
+            "go",
+        ];
+        for line in lines {
+            assert!(usi.handle(line));
+        }
+        assert!(!usi.handle("quit"));
+
+        let out = output(&usi);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[..7],
+            [
+                "id name gogo_shogi",
+                "id author toduq",
+                "option name USI_Hash type spin default 16 min 1 max 4096",
+                "option name Threads type spin default 1 min 1 max 256",
+                "option name Seed type string default random",
+                "usiok",
+                "readyok"
+            ]
+        );
+        let (bestmove, infos) = lines[7..].split_last().unwrap();
+        assert!(!infos.is_empty());
+        assert!(infos.iter().all(|line| line.starts_with("info depth ")));
+        assert!(bestmove.starts_with("bestmove "));
+        assert_eq!(usi.config.seed, Some(42));
+        assert_eq!(usi.config.threads, 2);
+    }
+
+    #[test]
+    fn setoption_out_of_range() {
+        let mut usi = Usi::new(Vec::new());
+        let hash = |mb| ["name", "USI_Hash", "value", mb];
+        assert!(usi.setoption(&hash("4097")).is_err());
+        assert!(usi.setoption(&hash("0")).is_err());
+        assert!(usi.setoption(&hash("1")).is_ok());
+        assert!(usi.setoption(&["name", "Threads", "value", "0"]).is_err());
+        assert!(usi.setoption(&["name", "Threads", "value", "257"]).is_err());
+    }
+
+    #[test]
+    fn go_infinite_until_stop() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position startpos");
+        usi.handle("go infinite");
+        thread::sleep(Duration::from_millis(100));
+        assert!(!output(&usi).contains("bestmove"));
+        usi.handle("stop");
+        assert!(output(&usi)
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("bestmove "));
+    }
+
+    #[test]
+    fn go_reports_each_iteration() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position startpos");
+        usi.handle("go depth 3");
+        // waits for the search without stopping it.
+        usi.search.take().unwrap().join().unwrap();
+        let out = output(&usi);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        for (depth, line) in lines[..3].iter().enumerate() {
+            assert!(line.starts_with(&format!("info depth {} score cp ", depth + 1)));
+        }
+        assert!(lines[3].starts_with("bestmove "));
+    }
+
+    #[test]
+    fn go_infinite_holds_mate_until_stop() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position sfen 4k/5/5/5/K4 b GGSSBBRRPP 1");
+        usi.handle("go infinite");
+        thread::sleep(Duration::from_millis(100));
+        assert!(output(&usi).starts_with("info depth "));
+        assert!(!output(&usi).contains("bestmove"));
+        usi.handle("stop");
+        assert!(output(&usi).ends_with("bestmove B*3c\n"));
+    }
+
+    #[test]
+    fn go_ponder_until_ponderhit() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position startpos");
+        usi.handle("go ponder btime 100 wtime 100");
+        thread::sleep(Duration::from_millis(200));
+        assert!(!output(&usi).contains("bestmove"));
+        usi.handle("ponderhit");
+        assert!(output(&usi)
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("bestmove "));
+    }
+
+    #[test]
+    fn go_reports_mate() {
+        let mut usi = Usi::new(Vec::new());
+        // the same tsume as in the searcher tests, which mates in 3 plies.
+        usi.handle("position sfen 1+P1kr/+R4/5/5/K4 b Sgs 1");
+        usi.handle("go depth 1");
+        usi.handle("quit");
+        let out = output(&usi);
+        let lines: Vec<&str> = out.lines().collect();
+        // the tsume solver proves it before the search.
+        assert!(lines[0].starts_with("info depth 3 score mate 3 nodes "));
+        assert!(lines[0].ends_with(" pv 4a3a 2a3a S*2b"));
+        assert_eq!(lines[1], "bestmove 4a3a");
+        assert_eq!(score_to_usi(searcher::mated_score(3)), "mate -3");
+        assert_eq!(score_to_usi(-120), "cp -120");
+    }
+
+    #[test]
+    fn go_parameters() {
+        let mut usi = Usi::new(Vec::new());
+        usi.handle("position startpos moves 2e3d");
+        let ms = Duration::from_millis;
+        let (config, limits) = usi
+            .go_params(&["btime", "1000", "wtime", "2000", "winc", "30"])
+            .unwrap();
+        assert_eq!(config.depth, SearchConfig::MAX_DEPTH);
+        assert_eq!(
+            limits,
+            Limits {
+                time: Some(ms(2000)),
+                increment: ms(30),
+                ..Limits::default()
+            }
+        );
+        let (config, limits) = usi
+            .go_params(&["byoyomi", "1000", "depth", "3", "nodes", "500"])
+            .unwrap();
+        assert_eq!(config.depth, 3);
+        assert_eq!(
+            limits,
+            Limits {
+                nodes: Some(500),
+                byoyomi: ms(1000),
+                ..Limits::default()
+            }
+        );
+        assert_eq!(
+            usi.go_params(&[]),
+            Ok((SearchConfig::default(), Limits::default()))
+        );
+        assert!(usi.go_params(&["movetime"]).is_err());
+        assert!(usi.go_params(&["depth", "0"]).is_err());
+        let (_, limits) = usi.go_params(&["infinite"]).unwrap();
+        assert!(limits.infinite);
+        let (_, limits) = usi.go_params(&["ponder", "wtime", "1000"]).unwrap();
+        assert!(limits.infinite);
+        assert!(usi.go_params(&["mate", "1000"]).is_err());
+    }
+}